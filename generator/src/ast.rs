@@ -1,129 +1,451 @@
-use std::fs::File;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::collections::HashMap;
-use std::{cmp, mem};
-use std::path::Path;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Token {
     Ident(String),
-    Int(u32),
+    Int(i64),
     String(String),
-    Spikey(Vec<Token>),
-    Square(Vec<Token>),
-    Braces(Vec<Token>),
-    Parens(Vec<Token>),
+    /// `[{ ... }]`, a code fragment.
+    Code(String),
+    /// `$name`, an operand name in a dag.
+    VarName(String),
+    Spikey(Vec<(Token, Span)>),
+    Square(Vec<(Token, Span)>),
+    Braces(Vec<(Token, Span)>),
+    Parens(Vec<(Token, Span)>),
     Colon,
     Comma,
     Semicolon,
     Equals,
     Bang,
+    Question,
+    Dot,
+    Paste,
 }
 
-fn tokenize(mut s: &str) -> Vec<Token> {
+impl fmt::Display for Token {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref s) => write!(fmt, "`{}`", s),
+            Token::Int(n) => write!(fmt, "`{}`", n),
+            Token::String(ref s) => write!(fmt, "`\"{}\"`", s),
+            Token::Code(_) => fmt.write_str("`[{...}]`"),
+            Token::VarName(ref s) => write!(fmt, "`${}`", s),
+            Token::Spikey(_) => fmt.write_str("`<...>`"),
+            Token::Square(_) => fmt.write_str("`[...]`"),
+            Token::Braces(_) => fmt.write_str("`{...}`"),
+            Token::Parens(_) => fmt.write_str("`(...)`"),
+            Token::Colon => fmt.write_str("`:`"),
+            Token::Comma => fmt.write_str("`,`"),
+            Token::Semicolon => fmt.write_str("`;`"),
+            Token::Equals => fmt.write_str("`=`"),
+            Token::Bang => fmt.write_str("`!`"),
+            Token::Question => fmt.write_str("`?`"),
+            Token::Dot => fmt.write_str("`.`"),
+            Token::Paste => fmt.write_str("`#`"),
+        }
+    }
+}
+
+/// A file of TableGen source.
+#[derive(PartialEq, Eq)]
+pub struct Source {
+    /// The path of the file, as shown in diagnostics.
+    pub name: String,
+    pub text: String,
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result { fmt::Debug::fmt(&self.name, fmt) }
+}
+
+/// Where a token starts: a line and column (in characters) of a
+/// source file, both counting from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// An error in TableGen source, and where it is, if that is known.
+#[derive(Debug)]
+pub struct ParseError {
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+pub type ParseResult<T> = Result<T, ParseError>;
+
+impl ParseError {
+    fn new(span: Span, message: String) -> ParseError {
+        ParseError { span: Some(span), message: message }
+    }
+}
+
+impl fmt::Display for ParseError {
+    /// Shows `file:line:col: message`, then the offending line with a
+    /// caret under the column.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let span = match self.span {
+            Some(ref span) => span,
+            None => return fmt.write_str(&self.message)
+        };
+        let line = span.source.text.lines().nth(span.line - 1).unwrap_or("");
+        // tabs are kept, so that the caret lines up
+        let indent = line.chars().take(span.col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        write!(fmt, "{}:{}:{}: {}\n    {}\n    {}^",
+               span.source.name, span.line, span.col, self.message, line, indent)
+    }
+}
+
+/// Splits `source` into tokens, nesting the groups in brackets and
+/// leaving out what the preprocessor skips. `defines` are the names
+/// `#define`d so far, and gains those defined in `source`.
+fn tokenize(source: &Rc<Source>, defines: &mut HashSet<String>)
+            -> ParseResult<Vec<(Token, Span)>> {
+    let text = &*source.text;
+    // the opening bracket of each enclosing group, where it is, and
+    // the tokens before it
     let mut stack = vec![];
     let mut current = vec![];
-    while !s.is_empty() && s.char_at(0).is_whitespace() {
-        // will fail with non-ascii whitespace
-        s = &s[1..]
-    }
-
-    while !s.is_empty() {
-        let length = match s.char_at(0) {
-            ':' => { current.push(Token::Colon); 1 },
-            ',' => { current.push(Token::Comma); 1 },
-            ';' => { current.push(Token::Semicolon); 1 },
-            '=' => { current.push(Token::Equals); 1 },
-            '!' => { current.push(Token::Bang); 1 }
-            '"' /*"*/=> {
-                let string = regex!("^\"(.*?)\"").captures(s).expect("bad string").at(1)
-                    .unwrap();
-                current.push(Token::String(string.to_string()));
-                string.len() + 2
-            }
-            '0'...'9' => {
-                let num = regex!("^[0-9]+").captures(s).expect("bad num").at(0).unwrap();
-                current.push(Token::Int(num.parse().unwrap()));
-                num.len()
-            }
-            '<' | '(' | '{' | '[' => {
-                let old = mem::replace(&mut current, vec![]);
-                stack.push(old);
-                1
-            }
-            '>' => {
-                let new = mem::replace(&mut current, stack.pop().expect("empty > stack?"));
-                current.push(Token::Spikey(new));
-                1
-            }
-            '}' => {
-                let new = mem::replace(&mut current, stack.pop().expect("empty } stack?"));
-                current.push(Token::Braces(new));
-                1
-            }
-            ']' => {
-                let new = mem::replace(&mut current, stack.pop().expect("empty ] stack?"));
-                current.push(Token::Square(new));
-                1
-            }
-            ')' => {
-                let new = mem::replace(&mut current, stack.pop().expect("empty ) stack?"));
-                current.push(Token::Parens(new));
-                1
-            }
-            // comment
-            '/' => {
-                s.chars().take_while(|c| *c != '\n').count()
-            }
-            c if c.is_whitespace() => 0,
-            _ => {
-                let ident = match regex!("[A-Za-z0-9_]+").captures(s) {
-                    Some(i) => i.at(0).unwrap(),
-                    None => panic!("invalid token stream, {}...", &s[..cmp::max(10, s.len())])
-                };
-                current.push(Token::Ident(ident.to_string()));
-                ident.len()
+    // each enclosing `#ifdef`/`#ifndef`: whether its lines are kept,
+    // whether its `#else` has been seen, and where it is
+    let mut conditions: Vec<(bool, bool, Span)> = vec![];
+    let mut pos = 0;
+    let (mut line, mut line_start) = (1, 0);
+
+    while pos < text.len() {
+        let s = &text[pos..];
+        let c = s.char_at(0);
+        if c.is_whitespace() {
+            if c == '\n' {
+                line += 1;
+                line_start = pos + 1;
+            }
+            pos += c.len_utf8();
+            continue
+        }
+        let span = Span {
+            source: source.clone(),
+            line: line,
+            col: text[line_start..pos].chars().count() + 1,
+        };
+        let active = conditions.iter().all(|&(keep, _, _)| keep);
+
+        // directives have to start their line, elsewhere `#` is a paste
+        let directive = if c == '#' && text[line_start..pos].trim().is_empty() {
+            regex!(r"^#(ifdef|ifndef|define|else|endif)\b[ \t]*([A-Za-z_][A-Za-z0-9_]*)?")
+                .captures(s)
+        } else {
+            None
+        };
+
+        let length = if let Some(caps) = directive {
+            let keyword = caps.at(1).unwrap();
+            match (keyword, caps.at(2)) {
+                ("ifdef", Some(name)) | ("ifndef", Some(name)) => {
+                    let keep = defines.contains(name) == (keyword == "ifdef");
+                    conditions.push((keep, false, span.clone()));
+                }
+                ("define", Some(name)) => {
+                    if active { defines.insert(name.to_string()); }
+                }
+                ("else", None) => {
+                    let cond = match conditions.last_mut() {
+                        Some(cond) => cond,
+                        None => return Err(ParseError::new(span, "`#else` without `#ifdef`".to_string()))
+                    };
+                    if cond.1 {
+                        return Err(ParseError::new(span, "`#else` after `#else`".to_string()))
+                    }
+                    cond.0 = !cond.0;
+                    cond.1 = true;
+                }
+                ("endif", None) => if conditions.pop().is_none() {
+                    return Err(ParseError::new(span, "`#endif` without `#ifdef`".to_string()))
+                },
+                _ => return Err(ParseError::new(span, format!("malformed `#{}`", keyword)))
+            }
+            // only a comment can follow a directive on its line
+            let len = caps.at(0).unwrap().len();
+            let rest = &s[len..];
+            let rest = rest[..rest.find('\n').unwrap_or(rest.len())].trim();
+            if !(rest.is_empty() || rest.starts_with("//") || rest.starts_with("/*")) {
+                return Err(ParseError::new(span, format!("unexpected `{}` after `#{}`",
+                                                         rest, keyword)))
+            }
+            len
+        } else if !active {
+            s.find('\n').unwrap_or(s.len())
+        } else {
+            match c {
+                ':' => { current.push((Token::Colon, span)); 1 },
+                ',' => { current.push((Token::Comma, span)); 1 },
+                ';' => { current.push((Token::Semicolon, span)); 1 },
+                '=' => { current.push((Token::Equals, span)); 1 },
+                '!' => { current.push((Token::Bang, span)); 1 }
+                '?' => { current.push((Token::Question, span)); 1 }
+                '.' => { current.push((Token::Dot, span)); 1 }
+                '#' => { current.push((Token::Paste, span)); 1 }
+                '"' /*"*/=> match string_literal(s) {
+                    Ok((string, len)) => { current.push((Token::String(string), span)); len }
+                    Err(msg) => return Err(ParseError::new(span, msg))
+                },
+                '0'...'9' | '-' | '+' => match number(s) {
+                    Ok((tok, len)) => { current.push((tok, span)); len }
+                    Err(msg) => return Err(ParseError::new(span, msg))
+                },
+                '$' => {
+                    let name = match regex!(r"^\$([A-Za-z_][A-Za-z0-9_]*)").captures(s) {
+                        Some(caps) => caps.at(1).unwrap(),
+                        None => return Err(ParseError::new(span, "unexpected character `$`".to_string()))
+                    };
+                    current.push((Token::VarName(name.to_string()), span));
+                    name.len() + 1
+                }
+                '[' if s.starts_with("[{") => {
+                    let end = match s.find("}]") {
+                        Some(end) => end,
+                        None => {
+                            return Err(ParseError::new(span, "unterminated code fragment".to_string()))
+                        }
+                    };
+                    current.push((Token::Code(s[2..end].to_string()), span));
+                    end + 2
+                }
+                '<' | '(' | '{' | '[' => {
+                    let outer = mem::replace(&mut current, vec![]);
+                    stack.push((c, span, outer));
+                    1
+                }
+                '>' | ')' | '}' | ']' => {
+                    let (open, open_span, outer) = match stack.pop() {
+                        Some(group) => group,
+                        None => return Err(ParseError::new(span, format!("unexpected `{}`", c)))
+                    };
+                    let close = match open { '<' => '>', '(' => ')', '{' => '}', _ => ']' };
+                    if c != close {
+                        return Err(ParseError::new(span, format!(
+                            "expected `{}` to close the `{}` at {}:{}, found `{}`",
+                            close, open, open_span.line, open_span.col, c)))
+                    }
+                    let contents = mem::replace(&mut current, outer);
+                    let tok = match c {
+                        '>' => Token::Spikey(contents),
+                        ')' => Token::Parens(contents),
+                        '}' => Token::Braces(contents),
+                        _ => Token::Square(contents),
+                    };
+                    current.push((tok, open_span));
+                    1
+                }
+                '/' if s.starts_with("//") => s.find('\n').unwrap_or(s.len()),
+                '/' if s.starts_with("/*") => match block_comment(s) {
+                    Some(len) => len,
+                    None => return Err(ParseError::new(span, "unterminated comment".to_string()))
+                },
+                _ => {
+                    let ident = match regex!("^[A-Za-z0-9_]+").captures(s) {
+                        Some(i) => i.at(0).unwrap(),
+                        None => {
+                            return Err(ParseError::new(span, format!("unexpected character `{}`", c)))
+                        }
+                    };
+                    current.push((Token::Ident(ident.to_string()), span));
+                    ident.len()
+                }
             }
         };
-        s = &s[length..];
-        while !s.is_empty() && s.char_at(0).is_whitespace() {
-            // will fail with non-ascii whitespace
-            s = &s[1..]
+        // comments, code fragments and skipped lines can cover
+        // several lines
+        let consumed = &s[..length];
+        if let Some(i) = consumed.rfind('\n') {
+            line += consumed.matches('\n').count();
+            line_start = pos + i + 1;
         }
+        pos += length;
     }
 
-    assert!(stack.is_empty());
-    current
+    if let Some((_, _, span)) = conditions.pop() {
+        return Err(ParseError::new(span, "unterminated `#ifdef`".to_string()))
+    }
+    match stack.pop() {
+        Some((open, span, _)) => Err(ParseError::new(span, format!("unclosed `{}`", open))),
+        None => Ok(current)
+    }
 }
 
-#[derive(Clone, Debug)]
+/// Reads the string literal at the start of `s`, returning its
+/// contents, with the escapes replaced, and its length.
+fn string_literal(s: &str) -> Result<(String, usize), String> {
+    let mut ret = String::new();
+    let mut chars = s.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((ret, i + 1)),
+            '\n' => break,
+            '\\' => match chars.next() {
+                Some((_, '\\')) => ret.push('\\'),
+                Some((_, '\'')) => ret.push('\''),
+                Some((_, '"')) => ret.push('"'),
+                Some((_, 't')) => ret.push('\t'),
+                Some((_, 'n')) => ret.push('\n'),
+                Some((_, c)) => return Err(format!("unknown escape `\\{}`", c)),
+                None => break
+            },
+            c => ret.push(c)
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Reads the integer at the start of `s` (`12`, `-3`, `0x1f` or
+/// `0b101`), or an identifier starting with digits, like the `8i` of
+/// `v#8i`, returning it and its length.
+fn number(s: &str) -> Result<(Token, usize), String> {
+    let (text, digits, radix) = if let Some(caps) = regex!("^0x([0-9a-fA-F]+)").captures(s) {
+        (caps.at(0).unwrap(), caps.at(1).unwrap(), 16)
+    } else if let Some(caps) = regex!("^0b([01]+)").captures(s) {
+        (caps.at(0).unwrap(), caps.at(1).unwrap(), 2)
+    } else if let Some(caps) = regex!("^[0-9]+[A-Za-z_][A-Za-z0-9_]*").captures(s) {
+        let ident = caps.at(0).unwrap();
+        return Ok((Token::Ident(ident.to_string()), ident.len()))
+    } else if let Some(caps) = regex!("^[-+]?[0-9]+").captures(s) {
+        let text = caps.at(0).unwrap();
+        return match text.trim_left_matches('+').parse() {
+            Ok(n) => Ok((Token::Int(n), text.len())),
+            Err(_) => Err(format!("integer `{}` is too large", text))
+        }
+    } else {
+        return Err(format!("unexpected character `{}`", s.char_at(0)))
+    };
+    // like `llvm-tblgen`, these are 64 bits, whatever their sign
+    match u64::from_str_radix(digits, radix) {
+        Ok(n) => Ok((Token::Int(n as i64), text.len())),
+        Err(_) => Err(format!("integer `{}` is too large", text))
+    }
+}
+
+/// The length of the (possibly nested) `/* ... */` comment at the
+/// start of `s`, if it is terminated.
+fn block_comment(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut pos = 0;
+    while pos < s.len() {
+        let rest = &s[pos..];
+        if rest.starts_with("/*") {
+            depth += 1;
+            pos += 2;
+        } else if rest.starts_with("*/") {
+            depth -= 1;
+            pos += 2;
+            if depth == 0 { return Some(pos) }
+        } else {
+            pos += rest.char_at(0).len_utf8();
+        }
+    }
+    None
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Val {
     List(Vec<Val>),
-    Strconcat(Vec<Val>),
     Type(Type),
     String(String),
-    Int(u32),
+    Int(i64),
+    /// `{a, b, ...}`, the bits of a `bits<n>` value, most significant
+    /// first, until they are all known.
+    Bits(Vec<Val>),
+    /// `?`, an uninitialized field.
+    Unset,
+    /// `value.Field`.
+    Field(Box<Val>, String),
+    /// `list[n]`.
+    Index(Box<Val>, u32),
+    /// `a # b # ...`, concatenating strings or lists.
+    Paste(Vec<Val>),
+    /// `!op(a, b, ...)` or `!op<T>(...)`, a bang operator that
+    /// couldn't be evaluated yet.
+    Bang(String, Option<Type>, Vec<Val>),
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Type {
     pub name: String,
     pub args: Vec<Val>
 }
 
+/// The contents of a `{ ... }` body.
+#[derive(Clone, Debug, Default)]
+pub struct Body {
+    /// The `defvar`s, only visible inside the body, in order. They're
+    /// evaluated along with the fields, where the template arguments
+    /// and other records are known.
+    pub vars: Vec<(String, Val)>,
+    /// The fields declared (`int X = 1;`, or `int X;` without a
+    /// value) and overridden (`let X = 1;`), in order.
+    pub fields: Vec<(String, Option<Val>)>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Class {
     pub name: String,
     pub args: Vec<(Type, String, Option<Val>)>,
-    pub inherits: Vec<Type>
+    pub inherits: Vec<Type>,
+    pub body: Body,
+    /// The `let` bindings enclosing this class, outermost first.
+    pub lets: Vec<(String, Val)>,
+    /// Where the class is named.
+    pub span: Span,
 }
 
 #[derive(Clone, Debug)]
 pub struct Def {
+    /// The name, once the def is instantiated.
     pub name: String,
+    /// The pieces of the name as written, e.g. `NAME`, `"_x"` for
+    /// `def NAME#"_x"`, to be filled in when instantiating.
+    pub name_parts: Vec<Val>,
     pub inherits: Vec<Type>,
+    pub body: Body,
+    /// The `let` bindings enclosing this def, outermost first.
+    pub lets: Vec<(String, Val)>,
+    /// The value of every field, once the classes are resolved.
+    pub fields: Vec<(String, Val)>,
+    /// Where the def is, to report errors instantiating it.
+    pub span: Span,
+}
+
+impl Def {
+    pub fn field(&self, name: &str) -> Option<&Val> {
+        self.fields.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v)
+    }
 }
+#[derive(Clone, Debug)]
+pub struct Multiclass {
+    pub name: String,
+    pub args: Vec<(Type, String, Option<Val>)>,
+    /// The multiclasses whose defs are included in this one.
+    pub inherits: Vec<Type>,
+    pub items: Vec<Item>,
+    /// Where the multiclass is named.
+    pub span: Span,
+}
+
 #[derive(Clone, Debug)]
 pub struct Let {
+    pub name: String,
+    pub value: Val,
     pub items: Vec<Item>,
 }
 
@@ -132,328 +454,1719 @@ pub struct Include {
     items: Vec<Item>,
 }
 
+/// `foreach var = list in { ... }`.
+#[derive(Clone, Debug)]
+pub struct Foreach {
+    pub var: String,
+    pub list: Val,
+    pub items: Vec<Item>,
+    /// Where the list is.
+    pub span: Span,
+}
+
+/// `defvar name = value;`, visible to the items after it.
+#[derive(Clone, Debug)]
+pub struct Defvar {
+    pub name: String,
+    pub value: Val,
+}
+
+/// `defset list<T> name = { ... }`, naming the list of the defs in
+/// `items`.
+#[derive(Clone, Debug)]
+pub struct Defset {
+    pub name: String,
+    pub items: Vec<Item>,
+}
+
+/// `if cond then { ... } else { ... }`.
+#[derive(Clone, Debug)]
+pub struct If {
+    pub cond: Val,
+    pub then: Vec<Item>,
+    pub otherwise: Vec<Item>,
+    /// Where the condition is.
+    pub span: Span,
+}
+
 #[derive(Clone, Debug)]
 pub enum Item {
     Class(Class),
-    Multiclass(Class),
+    Multiclass(Multiclass),
     Def(Def),
     Defm(Def),
     Let(Let),
     Include(Include),
+    Foreach(Foreach),
+    Defvar(Defvar),
+    Defset(Defset),
+    If(If),
+}
+
+/// Instantiates every def in `items` in order, expanding `defm`s and
+/// `foreach`es, and resolving the superclasses and fields of each.
+pub fn evaluate(items: &[Item]) -> ParseResult<Vec<Def>> {
+    let mut records = Records::default();
+    try!(records.instantiate(items, &HashMap::new(), &mut vec![], &[]));
+    Ok(records.defs)
 }
 
-pub fn flatten_separate(items: Vec<Item>) -> (Vec<Class>, Vec<Def>) {
-    let mut classes = vec![];
-    let mut defs = vec![];
-    for it in items.into_iter() {
-        match it {
-            Item::Let(Let { items }) | Item::Include(Include { items }) => {
-                let (c, d) = flatten_separate(items);
-                classes.extend(c.into_iter());
-                defs.extend(d.into_iter());
+/// The classes, multiclasses and defs defined so far, which later
+/// items and values can refer to.
+#[derive(Default)]
+struct Records {
+    classes: HashMap<String, Class>,
+    multiclasses: HashMap<String, Rc<Multiclass>>,
+    defs: Vec<Def>,
+    def_index: HashMap<String, usize>,
+    /// The list of defs named by each `defset`.
+    defsets: HashMap<String, Val>,
+    /// The fields of the anonymous class instances evaluated so far.
+    instances: RefCell<HashMap<Type, Rc<Vec<(String, Val)>>>>,
+    /// The number of defs named `anonymous_<n>` so far.
+    anonymous: usize,
+}
+
+impl Records {
+    /// Instantiates `items`, with `rules` giving the values of the
+    /// variables in scope (template arguments, `NAME`, `foreach` and
+    /// `defvar` variables). Every resulting def also inherits from
+    /// `extra`, the plain classes of the enclosing `defm`s.
+    fn instantiate(&mut self, items: &[Item], rules: &HashMap<&String, &Val>,
+                   lets: &mut Vec<(String, Val)>, extra: &[Type]) -> ParseResult<()> {
+        for (i, it) in items.iter().enumerate() {
+            match *it {
+                Item::Class(ref c) => {
+                    let mut c = c.clone();
+                    if !rules.is_empty() {
+                        // the template arguments and `defvar`s shadow
+                        // the variables
+                        let rules = rules.iter()
+                            .filter(|&(name, _)| !c.args.iter().any(|a| a.1 == **name) &&
+                                                 !c.body.vars.iter().any(|v| v.0 == **name))
+                            .map(|(&n, &v)| (n, v))
+                            .collect();
+                        for var in c.body.vars.iter_mut() {
+                            var.1 = self.eval(&var.1, &rules)
+                        }
+                        for field in c.body.fields.iter_mut() {
+                            field.1 = field.1.as_ref().map(|v| self.eval(v, &rules))
+                        }
+                    }
+                    c.lets = lets.clone();
+                    if self.classes.contains_key(&c.name) {
+                        return Err(ParseError::new(c.span.clone(),
+                                                   format!("class `{}` is defined twice", c.name)))
+                    }
+                    self.classes.insert(c.name.clone(), c);
+                }
+                Item::Multiclass(ref m) => {
+                    self.multiclasses.insert(m.name.clone(), Rc::new(m.clone()));
+                }
+                Item::Def(ref d) => {
+                    let mut inherits = d.inherits.iter()
+                        .map(|ty| self.eval_type(ty, rules))
+                        .collect::<Vec<_>>();
+                    inherits.extend(extra.iter().cloned());
+                    let d = Def {
+                        name: try!(self.def_name(d, rules)),
+                        name_parts: vec![],
+                        inherits: inherits,
+                        body: Body { vars: vec![], fields: self.eval_body(&d.body, rules) },
+                        lets: lets.clone(),
+                        fields: vec![],
+                        span: d.span.clone(),
+                    };
+                    try!(self.add(d))
+                }
+                Item::Defm(ref d) => try!(self.defm(d, rules, lets, extra)),
+                Item::Let(Let { ref name, ref value, ref items }) => {
+                    lets.push((name.clone(), self.eval(value, rules)));
+                    let res = self.instantiate(items, rules, lets, extra);
+                    lets.pop();
+                    try!(res)
+                }
+                Item::Include(Include { ref items }) => try!(self.instantiate(items, rules, lets, extra)),
+                Item::Foreach(Foreach { ref var, ref list, ref items, ref span }) => {
+                    let vals = match self.eval(list, rules) {
+                        Val::List(vals) => vals,
+                        v => return Err(ParseError::new(span.clone(),
+                            format!("`foreach {}` over {:?}, which isn't a list", var, v)))
+                    };
+                    for val in vals.iter() {
+                        try!(self.instantiate(items, &bind(rules, var, val), lets, extra))
+                    }
+                }
+                Item::Defvar(Defvar { ref name, ref value }) => {
+                    let value = self.eval(value, rules);
+                    return self.instantiate(&items[i + 1..], &bind(rules, name, &value), lets, extra)
+                }
+                Item::If(If { ref cond, ref then, ref otherwise, ref span }) => {
+                    let items = match self.eval(cond, rules) {
+                        Val::Int(0) => otherwise,
+                        Val::Int(_) => then,
+                        v => return Err(ParseError::new(span.clone(),
+                            format!("`if` on {:?}, which isn't a bit", v)))
+                    };
+                    try!(self.instantiate(items, rules, lets, extra))
+                }
+                Item::Defset(Defset { ref name, ref items }) => {
+                    let start = self.defs.len();
+                    try!(self.instantiate(items, rules, lets, extra));
+                    let defs = self.defs[start..].iter()
+                        .map(|d| Val::Type(Type { name: d.name.clone(), args: vec![] }))
+                        .collect();
+                    self.defsets.insert(name.clone(), Val::List(defs));
+                }
             }
-            Item::Defm(_) | Item::Multiclass(_) => {}
-            Item::Class(c) => classes.push(c),
-            Item::Def(d) => defs.push(d),
         }
+        Ok(())
     }
-    (classes, defs)
-}
 
-pub fn classes_by_name(classes: &[Class]) -> HashMap<&str, &Class> {
-    let mut map = HashMap::new();
-    for c in classes.iter() {
-        assert!(map.insert(&*c.name, c).is_none());
+    /// Resolves the superclasses and fields of `d`, and records it.
+    fn add(&mut self, mut d: Def) -> ParseResult<()> {
+        let (inherits, fields) = match self.resolve(&d.inherits, &d.lets, &d.body, &d.name) {
+            Ok(resolved) => resolved,
+            Err(message) => return Err(ParseError::new(d.span.clone(), message))
+        };
+        d.inherits = inherits;
+        d.fields = fields;
+        self.def_index.insert(d.name.clone(), self.defs.len());
+        self.defs.push(d);
+        Ok(())
     }
-    map
-}
 
-pub fn resolve_classes(defs: &mut [Def], classes: &HashMap<&str, &Class>) {
-    for d in defs.iter_mut() {
-        let empty = HashMap::new();
-        let mut new = vec![];
-        for sup in d.inherits.iter() {
-            substitute_type(&mut new, sup, classes, &empty)
+    /// Instantiates the multiclasses that `defm` inherits from. Every
+    /// resulting def also inherits from `extra` and the plain classes
+    /// of `defm`.
+    fn defm(&mut self, defm: &Def, rules: &HashMap<&String, &Val>,
+            lets: &mut Vec<(String, Val)>, extra: &[Type]) -> ParseResult<()> {
+        let name = try!(self.def_name(defm, rules));
+        let (multis, classes): (Vec<_>, Vec<_>) = defm.inherits.iter()
+            .map(|ty| self.eval_type(ty, rules))
+            .partition(|ty| self.multiclasses.contains_key(&ty.name));
+        if multis.is_empty() {
+            return Err(ParseError::new(defm.span.clone(),
+                                       format!("`defm {}` doesn't inherit from a multiclass", name)))
+        }
+        if let Some(ty) = classes.iter().find(|ty| !self.classes.contains_key(&ty.name)) {
+            return Err(ParseError::new(defm.span.clone(),
+                                       format!("unknown multiclass or class `{}`", ty.name)))
+        }
+        let mut extra = extra.to_vec();
+        extra.extend(classes.into_iter());
+
+        for ty in multis.iter() {
+            let multi = self.multiclasses[&ty.name].clone();
+            try!(self.multiclass(&multi, ty, &name, lets, &extra, &defm.span))
         }
-        d.inherits = new;
+        Ok(())
     }
 
-    fn substitute_type(types: &mut Vec<Type>, ty: &Type, classes: &HashMap<&str, &Class>,
-                       args: &HashMap<&String, &Val>) {
+    /// Instantiates `multi` as `ty` for a `defm` named `name`, which is
+    /// at `span`.
+    fn multiclass(&mut self, multi: &Multiclass, ty: &Type, name: &str,
+                  lets: &mut Vec<(String, Val)>, extra: &[Type], span: &Span) -> ParseResult<()> {
         use std::iter::repeat;
 
-        let class = match classes.get(&*ty.name) {
-            None => {types.push(ty.clone()); return}
+        // each argument can refer to `NAME` and the earlier ones
+        let name_key = "NAME".to_string();
+        let name_val = Val::String(name.to_string());
+        let mut bound = vec![];
+        for (given, &(_, ref arg, ref default)) in ty.args.iter().map(Some).chain(repeat(None))
+                                                    .zip(multi.args.iter()) {
+            let val = {
+                let mut rules = HashMap::new();
+                rules.insert(&name_key, &name_val);
+                for &(ref n, ref v) in bound.iter() { rules.insert(n, v); }
+                match (given, default.as_ref()) {
+                    (Some(v), _) => v.clone(),
+                    (None, Some(v)) => self.eval(v, &rules),
+                    (None, None) => {
+                        return Err(ParseError::new(span.clone(),
+                            format!("missing argument `{}` to `{}`", arg, multi.name)))
+                    }
+                }
+            };
+            bound.push((arg.clone(), val));
+        }
+        let mut rules = HashMap::new();
+        rules.insert(&name_key, &name_val);
+        for &(ref n, ref v) in bound.iter() { rules.insert(n, v); }
+
+        for sup in multi.inherits.iter() {
+            let sup = self.eval_type(sup, &rules);
+            let base = match self.multiclasses.get(&sup.name) {
+                Some(base) => base.clone(),
+                None => return Err(ParseError::new(multi.span.clone(),
+                    format!("`{}` inherits from `{}`, which isn't a multiclass",
+                            multi.name, sup.name)))
+            };
+            try!(self.multiclass(&base, &sup, name, lets, extra, span))
+        }
+        self.instantiate(&multi.items, &rules, lets, extra)
+    }
+
+    /// The name of `d`, a def or defm instantiated with `rules`. Like
+    /// `llvm-tblgen`, `NAME` is implicitly prepended if it isn't used,
+    /// and defs without a name are numbered, `anonymous_<n>`.
+    fn def_name(&mut self, d: &Def, rules: &HashMap<&String, &Val>) -> ParseResult<String> {
+        let name_key = "NAME".to_string();
+        let mut name = String::new();
+        match rules.get(&name_key) {
+            Some(&&Val::String(ref prefix)) if !d.name_parts.iter().any(mentions_name) => {
+                name.push_str(prefix)
+            }
+            _ => {}
+        }
+        for part in d.name_parts.iter() {
+            let part = self.eval(part, rules);
+            if !push_name(&mut name, &part) {
+                return Err(ParseError::new(d.span.clone(),
+                    format!("invalid piece of the name of `{}`: {:?}", name, part)))
+            }
+        }
+        if name.is_empty() {
+            name = format!("anonymous_{}", self.anonymous);
+            self.anonymous += 1;
+        }
+        Ok(name)
+    }
+
+    /// The full list of ancestors of a record inheriting from
+    /// `inherits`, with their template arguments filled in, and its
+    /// fields.
+    ///
+    /// Like `llvm-tblgen`, fields are set by each superclass in order,
+    /// then the enclosing `let`s, then the record's own body, and each
+    /// class's fields are computed the same way.
+    fn resolve(&self, inherits: &[Type], lets: &[(String, Val)], body: &Body,
+               name: &str) -> Result<(Vec<Type>, Vec<(String, Val)>), String> {
+        let empty = HashMap::new();
+        let mut types = vec![];
+        let mut fields = vec![];
+        for sup in inherits.iter() {
+            try!(self.inherit(&mut types, &mut fields, sup, &empty))
+        }
+        for &(ref name, ref val) in lets.iter() {
+            set_field(&mut fields, name, Some(val.clone()))
+        }
+        for &(ref name, ref val) in body.fields.iter() {
+            set_field(&mut fields, name, val.clone())
+        }
+
+        // fields can refer to each other, e.g. `int Y = X;`, and to
+        // the name of the record, possibly through several steps
+        let name_key = "NAME".to_string();
+        let name_val = Val::String(name.to_string());
+        for _ in 0..fields.len() + 1 {
+            let resolved = {
+                let mut rules = fields.iter().map(|&(ref name, ref val)| (name, val))
+                    .collect::<HashMap<_, _>>();
+                if !rules.contains_key(&name_key) {
+                    rules.insert(&name_key, &name_val);
+                }
+                fields.iter().map(|&(ref name, ref val)| (name.clone(), self.eval(val, &rules)))
+                    .collect::<Vec<_>>()
+            };
+            if resolved == fields { break }
+            fields = resolved;
+        }
+        Ok((types, fields))
+    }
+
+    /// The fields of `body`, evaluated with its `defvar`s bound, in
+    /// turn, on top of `rules`.
+    fn eval_body(&self, body: &Body, rules: &HashMap<&String, &Val>)
+                 -> Vec<(String, Option<Val>)> {
+        let mut vars = vec![];
+        for &(ref name, ref val) in body.vars.iter() {
+            let val = {
+                let mut rules = rules.iter().map(|(&n, &v)| (n, v)).collect::<HashMap<_, _>>();
+                for &(n, ref v) in vars.iter() { rules.insert(n, v); }
+                self.eval(val, &rules)
+            };
+            vars.push((name, val));
+        }
+        let mut rules = rules.iter().map(|(&n, &v)| (n, v)).collect::<HashMap<_, _>>();
+        for &(n, ref v) in vars.iter() { rules.insert(n, v); }
+        body.fields.iter()
+            .map(|&(ref n, ref v)| (n.clone(), v.as_ref().map(|v| self.eval(v, &rules))))
+            .collect()
+    }
+
+    fn inherit(&self, types: &mut Vec<Type>, fields: &mut Vec<(String, Val)>,
+               ty: &Type, args: &HashMap<&String, &Val>) -> Result<(), String> {
+        let class = match self.classes.get(&ty.name) {
+            None => {types.push(ty.clone()); return Ok(())}
             Some(c) => c,
         };
-        let this_args = ty.args.iter().map(Some).chain(repeat(None));
 
-        let real_args = this_args.zip(class.args.iter()).map(|(left, right)| {
-            left.map(|v| substitute(v, args))
-                .or_else(|| right.2.clone())
-                .expect("arg with no val")
-        }).collect::<Vec<_>>();
+        // the defaults can refer to the earlier arguments
+        let mut real_args = vec![];
+        for (i, &(_, ref arg, ref default)) in class.args.iter().enumerate() {
+            let val = match (ty.args.get(i), default.as_ref()) {
+                (Some(v), _) => self.eval(v, args),
+                (None, Some(v)) => {
+                    let rules = real_args.iter().zip(class.args.iter())
+                        .map(|(val, a)| (&a.1, val))
+                        .collect();
+                    self.eval(v, &rules)
+                }
+                (None, None) => return Err(format!("missing argument `{}` to `{}`", arg, class.name))
+            };
+            real_args.push(val);
+        }
         {
             let arg_dict = real_args.iter().zip(class.args.iter()).map(|(val, right)| {
                 (&right.1, val)
             }).collect();
             for sup in class.inherits.iter() {
-                substitute_type(types, sup, classes, &arg_dict)
+                try!(self.inherit(types, fields, sup, &arg_dict))
+            }
+            for &(ref name, ref val) in class.lets.iter() {
+                set_field(fields, name, Some(val.clone()))
+            }
+            for (name, val) in self.eval_body(&class.body, &arg_dict) {
+                set_field(fields, &name, val)
             }
         }
 
         types.push(Type {
             name: ty.name.clone(),
-            args: real_args.clone(),
+            args: real_args,
         });
+        Ok(())
+    }
+
+    /// The def that `ty` refers to, if it is one.
+    fn def(&self, ty: &Type) -> Option<&Def> {
+        if !ty.args.is_empty() { return None }
+        self.def_index.get(&ty.name).map(|&i| &self.defs[i])
+    }
+
+    /// The field `name` of the record `val`, if it is known: either a
+    /// def, or an anonymous instance of a class, `C<a, b>.name`.
+    fn field(&self, val: &Val, name: &str) -> Option<Val> {
+        let ty = match *val {
+            Val::Type(ref ty) => ty,
+            _ => return None
+        };
+        if let Some(d) = self.def(ty) {
+            return d.field(name).cloned()
+        }
+        if !self.classes.contains_key(&ty.name) || !self.is_concrete(val) {
+            return None
+        }
+        let cached = self.instances.borrow().get(ty).cloned();
+        let fields = match cached {
+            Some(fields) => fields,
+            None => {
+                // an instance that can't be resolved (e.g. without an
+                // argument) is left unevaluated
+                let fields = match self.resolve(&[ty.clone()], &[], &Body::default(), "") {
+                    Ok((_, fields)) => Rc::new(fields),
+                    Err(_) => return None
+                };
+                self.instances.borrow_mut().insert(ty.clone(), fields.clone());
+                fields
+            }
+        };
+        fields.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| v.clone())
+    }
+
+    /// Whether the record `ty` is an instance of `class`.
+    fn isa(&self, ty: &Type, class: &str) -> bool {
+        match self.def(ty) {
+            Some(d) => d.inherits.iter().any(|t| t.name == class),
+            None => ty.name == class || self.classes.get(&ty.name).map_or(false, |c| {
+                c.inherits.iter().any(|t| self.isa(t, class))
+            })
+        }
     }
 
-    fn substitute(val: &Val, rules: &HashMap<&String, &Val>) -> Val {
+    /// Whether `val` is completely known, without any references to
+    /// variables or fields, or operators left to evaluate.
+    fn is_concrete(&self, val: &Val) -> bool {
         match *val {
-            Val::Type(ref ty) => match rules.get(&ty.name) {
-                Some(new) => {assert!(ty.args.is_empty()); (*new).clone()},
-                None => val.clone()
-            },
+            Val::String(_) | Val::Int(_) | Val::Unset => true,
+            Val::List(ref vals) | Val::Bits(ref vals) => vals.iter().all(|v| self.is_concrete(v)),
+            Val::Type(ref ty) => {
+                self.def(ty).is_some() ||
+                    (self.classes.contains_key(&ty.name) &&
+                     ty.args.iter().all(|v| self.is_concrete(v)))
+            }
+            Val::Field(..) | Val::Index(..) | Val::Paste(_) | Val::Bang(..) => false,
+        }
+    }
+
+    fn eval_type(&self, ty: &Type, rules: &HashMap<&String, &Val>) -> Type {
+        Type {
+            name: ty.name.clone(),
+            args: ty.args.iter().map(|v| self.eval(v, rules)).collect(),
+        }
+    }
+
+    /// Replaces the references to the names in `rules` in `val` by
+    /// their values, and evaluates the fields and operators that are
+    /// known. Anything else is left as is, to be evaluated once more
+    /// is known.
+    fn eval(&self, val: &Val, rules: &HashMap<&String, &Val>) -> Val {
+        match *val {
+            Val::Type(ref ty) if ty.args.is_empty() => {
+                match rules.get(&ty.name).map(|v| *v).or_else(|| self.defsets.get(&ty.name)) {
+                    Some(new) => new.clone(),
+                    None => val.clone(),
+                }
+            }
+            Val::Type(ref ty) => Val::Type(self.eval_type(ty, rules)),
             Val::List(ref vals) => {
-                Val::List(vals.iter().map(|v| substitute(v, rules)).collect())
+                Val::List(vals.iter().map(|v| self.eval(v, rules)).collect())
             }
-            Val::Strconcat(ref vals) => {
-                let new = vals.iter().map(|v| substitute(v, rules)).collect::<Vec<_>>();
-                if new.iter().all(|s| match *s { Val::String(_) => true, _ => false }) {
-                    Val::String(new.iter().map(|s| match *s {
-                        Val::String(ref s) => &**s, _ => unreachable!() }).collect())
+            Val::Bits(ref vals) => {
+                let new = vals.iter().map(|v| self.eval(v, rules)).collect::<Vec<_>>();
+                if new.iter().all(|v| *v == Val::Int(0) || *v == Val::Int(1)) {
+                    Val::Int(new.iter().fold(0, |n, v| n << 1 | (*v == Val::Int(1)) as i64))
                 } else {
-                    Val::Strconcat(new)
+                    Val::Bits(new)
                 }
             }
+            Val::Field(ref val, ref name) => {
+                let val = self.eval(val, rules);
+                match self.field(&val, name) {
+                    Some(field) => field,
+                    None => Val::Field(Box::new(val), name.clone())
+                }
+            }
+            Val::Index(ref val, n) => match self.eval(val, rules) {
+                Val::List(ref vals) if (n as usize) < vals.len() => vals[n as usize].clone(),
+                val => Val::Index(Box::new(val), n)
+            },
+            Val::Paste(ref vals) => {
+                let new = vals.iter().map(|v| self.eval(v, rules)).collect::<Vec<_>>();
+                if new.iter().all(|s| match *s { Val::String(_) | Val::Int(_) => true, _ => false }) {
+                    let mut s = String::new();
+                    for v in new.iter() { push_name(&mut s, v); }
+                    Val::String(s)
+                } else if new.iter().all(|s| match *s { Val::List(_) => true, _ => false }) {
+                    Val::List(new.into_iter().flat_map(|s| match s {
+                        Val::List(vals) => vals.into_iter(), _ => unreachable!() }).collect())
+                } else {
+                    Val::Paste(new)
+                }
+            }
+            Val::Bang(ref op, ref ty, ref args) => {
+                let (vars, body) = bound_vars(op, args.len());
+                let shadowed = rules.iter()
+                    .filter(|&(name, _)| !vars.iter().any(|&i| args[i] == var(name)))
+                    .map(|(&n, &v)| (n, v))
+                    .collect();
+                let args = args.iter().enumerate().map(|(i, a)| {
+                    if vars.contains(&i) {
+                        a.clone()
+                    } else if Some(i) == body {
+                        self.eval(a, &shadowed)
+                    } else {
+                        self.eval(a, rules)
+                    }
+                }).collect::<Vec<_>>();
+                match self.apply(op, ty.as_ref(), &args) {
+                    Some(val) => val,
+                    None => Val::Bang(op.clone(), ty.clone(), args)
+                }
+            }
+            Val::String(_) | Val::Int(_) | Val::Unset => val.clone(),
+        }
+    }
+
+    /// Applies the bang operator `!op<ty>` to `args`, if they are
+    /// known well enough.
+    fn apply(&self, op: &str, ty: Option<&Type>, args: &[Val]) -> Option<Val> {
+        use std::iter::repeat;
 
-            _ => val.clone()
+        fn int(val: &Val) -> Option<i64> {
+            match *val { Val::Int(n) => Some(n), _ => None }
+        }
+        fn bit(b: bool) -> Option<Val> { Some(Val::Int(b as i64)) }
+        let ints = || args.iter().map(int).collect::<Option<Vec<_>>>();
+        let all_concrete = args.iter().all(|v| self.is_concrete(v));
+
+        match (op, args.len()) {
+            ("add", _) => ints().map(|ns| Val::Int(ns.iter().fold(0, |a, &b| a.wrapping_add(b)))),
+            ("mul", _) => ints().map(|ns| Val::Int(ns.iter().fold(1, |a, &b| a.wrapping_mul(b)))),
+            ("and", _) => ints().map(|ns| Val::Int(ns.iter().fold(!0, |a, &b| a & b))),
+            ("or", _) => ints().map(|ns| Val::Int(ns.iter().fold(0, |a, &b| a | b))),
+            ("sub", 2) => ints().map(|ns| Val::Int(ns[0].wrapping_sub(ns[1]))),
+            ("shl", 2) => ints().map(|ns| Val::Int(ns[0].wrapping_shl(ns[1] as u32))),
+            ("sra", 2) => ints().map(|ns| Val::Int(ns[0].wrapping_shr(ns[1] as u32))),
+            ("srl", 2) => ints().map(|ns| Val::Int((ns[0] as u64).wrapping_shr(ns[1] as u32) as i64)),
+            ("not", 1) => int(&args[0]).and_then(|n| bit(n == 0)),
+            ("eq", 2) | ("ne", 2) if all_concrete => bit((args[0] == args[1]) == (op == "eq")),
+            ("lt", 2) | ("le", 2) | ("gt", 2) | ("ge", 2) => {
+                let ord = match (&args[0], &args[1]) {
+                    (&Val::Int(a), &Val::Int(b)) => a.cmp(&b),
+                    (&Val::String(ref a), &Val::String(ref b)) => a.cmp(b),
+                    _ => return None
+                };
+                bit(match op {
+                    "lt" => ord == Ordering::Less,
+                    "le" => ord != Ordering::Greater,
+                    "gt" => ord == Ordering::Greater,
+                    _ => ord != Ordering::Less,
+                })
+            }
+            ("if", 3) => int(&args[0]).map(|c| args[if c != 0 {1} else {2}].clone()),
+            ("cond", _) => {
+                for pair in args.chunks(2) {
+                    match int(&pair[0]) {
+                        Some(0) => {}
+                        Some(_) if pair.len() == 2 => return Some(pair[1].clone()),
+                        _ => return None
+                    }
+                }
+                None
+            }
+            ("size", 1) | ("empty", 1) => {
+                let len = match args[0] {
+                    Val::List(ref vals) => vals.len(),
+                    Val::String(ref s) => s.len(),
+                    _ => return None
+                };
+                if op == "size" { Some(Val::Int(len as i64)) } else { bit(len == 0) }
+            }
+            ("head", 1) => match args[0] {
+                Val::List(ref vals) => vals.first().cloned(),
+                _ => None
+            },
+            ("tail", 1) => match args[0] {
+                Val::List(ref vals) if !vals.is_empty() => Some(Val::List(vals[1..].to_vec())),
+                _ => None
+            },
+            ("listconcat", _) => {
+                let mut ret = vec![];
+                for v in args.iter() {
+                    match *v {
+                        Val::List(ref vals) => ret.extend(vals.iter().cloned()),
+                        _ => return None
+                    }
+                }
+                Some(Val::List(ret))
+            }
+            ("listsplat", 2) => int(&args[1]).map(|n| {
+                Val::List(repeat(args[0].clone()).take(n as usize).collect())
+            }),
+            ("strconcat", _) => {
+                let mut ret = String::new();
+                for v in args.iter() {
+                    match *v {
+                        Val::String(ref s) => ret.push_str(s),
+                        _ => return None
+                    }
+                }
+                Some(Val::String(ret))
+            }
+            ("subst", 3) => match (&args[0], &args[1], &args[2]) {
+                (&Val::String(ref from), &Val::String(ref to), &Val::String(ref s)) => {
+                    Some(Val::String(s.replace(&**from, to)))
+                }
+                (from, to, val) if all_concrete => {
+                    Some(if val == from { to.clone() } else { val.clone() })
+                }
+                _ => None
+            },
+            ("cast", 1) => {
+                let ty = match ty { Some(ty) => ty, None => return None };
+                match (&*ty.name, &args[0]) {
+                    ("string", &Val::String(_)) | ("int", &Val::Int(_)) |
+                    ("bit", &Val::Int(_)) | ("bits", &Val::Int(_)) => Some(args[0].clone()),
+                    ("string", &Val::Int(n)) => Some(Val::String(n.to_string())),
+                    ("string", &Val::Type(ref t)) if self.def(t).is_some() => {
+                        Some(Val::String(t.name.clone()))
+                    }
+                    ("string", _) | ("int", _) | ("bit", _) | ("bits", _) => None,
+                    // a record, looked up by name
+                    (_, &Val::String(ref name)) => {
+                        Some(Val::Type(Type { name: name.clone(), args: vec![] }))
+                    }
+                    (_, &Val::Type(_)) if all_concrete => Some(args[0].clone()),
+                    _ => None
+                }
+            }
+            ("isa", 1) => match (ty, &args[0]) {
+                (Some(ty), &Val::Type(ref t)) if all_concrete => bit(self.isa(t, &ty.name)),
+                _ => None
+            },
+            ("foreach", 3) | ("filter", 3) => {
+                let vals = match args[1] { Val::List(ref vals) => vals, _ => return None };
+                let mut ret = vec![];
+                for v in vals.iter() {
+                    let rules = Some((var_name(&args[0]), v)).into_iter().collect();
+                    let res = self.eval(&args[2], &rules);
+                    if op == "foreach" {
+                        ret.push(res)
+                    } else {
+                        match int(&res) {
+                            Some(0) => {}
+                            Some(_) => ret.push(v.clone()),
+                            None => return None
+                        }
+                    }
+                }
+                Some(Val::List(ret))
+            }
+            ("foldl", 5) => {
+                let vals = match args[1] { Val::List(ref vals) => vals, _ => return None };
+                let mut acc = args[0].clone();
+                for v in vals.iter() {
+                    acc = {
+                        let mut rules = HashMap::new();
+                        rules.insert(var_name(&args[2]), &acc);
+                        rules.insert(var_name(&args[3]), v);
+                        self.eval(&args[4], &rules)
+                    };
+                }
+                Some(acc)
+            }
+            _ => None
         }
     }
 }
 
-struct Parser<'a, I: Iterator<Item = Token>> {
-    tokens: I,
-    root: &'a Path
+fn set_field(fields: &mut Vec<(String, Val)>, name: &str, val: Option<Val>) {
+    match fields.iter().position(|&(ref n, _)| n == name) {
+        // a declaration without a value doesn't reset the field
+        Some(i) => if let Some(val) = val { fields[i].1 = val },
+        None => fields.push((name.to_string(), val.unwrap_or(Val::Unset))),
+    }
+}
+
+/// `rules`, with `name` bound to `val` too.
+fn bind<'a>(rules: &HashMap<&'a String, &'a Val>,
+            name: &'a String, val: &'a Val) -> HashMap<&'a String, &'a Val> {
+    let mut rules = rules.clone();
+    rules.insert(name, val);
+    rules
+}
+
+/// A reference to the variable `name`.
+fn var(name: &str) -> Val {
+    Val::Type(Type { name: name.to_string(), args: vec![] })
+}
+
+/// The indices of the arguments of `!op` (with `n` arguments) that
+/// name variables, and of the one they are bound in:
+/// `!foreach(x, list, expr)`, `!filter(x, list, pred)` and
+/// `!foldl(init, list, acc, x, expr)` bind variables in their last
+/// operand. These are constants, since a `&[...]` in an arm would need
+/// promoting to a static.
+fn bound_vars(op: &str, n: usize) -> (&'static [usize], Option<usize>) {
+    const NONE: &'static [usize] = &[];
+    const FIRST: &'static [usize] = &[0];
+    const THIRD_AND_FOURTH: &'static [usize] = &[2, 3];
+    match (op, n) {
+        ("foreach", 3) | ("filter", 3) => (FIRST, Some(2)),
+        ("foldl", 5) => (THIRD_AND_FOURTH, Some(4)),
+        _ => (NONE, None),
+    }
+}
+
+/// The name of the variable bound by an operator like `!foreach`,
+/// which the parser has checked is one.
+fn var_name(val: &Val) -> &String {
+    match *val {
+        Val::Type(ref ty) if ty.args.is_empty() => &ty.name,
+        _ => unreachable!()
+    }
 }
 
-fn expect(t: Token, expected: Token) {
-    if t != expected {
-        panic!("expected {:?}, found {:?}", expected, t)
+/// Appends the string, number or identifier `val` to `name`,
+/// returning false if it is something else.
+fn push_name(name: &mut String, val: &Val) -> bool {
+    match *val {
+        Val::String(ref s) => name.push_str(s),
+        Val::Int(n) => name.push_str(&n.to_string()),
+        Val::Type(ref ty) if ty.args.is_empty() => name.push_str(&ty.name),
+        Val::Paste(ref vals) => return vals.iter().all(|v| push_name(name, v)),
+        _ => return false
     }
+    true
+}
+
+fn mentions_name(val: &Val) -> bool {
+    match *val {
+        Val::Type(ref ty) => ty.name == "NAME" || ty.args.iter().any(mentions_name),
+        Val::List(ref vals) | Val::Bits(ref vals) | Val::Paste(ref vals) |
+        Val::Bang(_, _, ref vals) => {
+            vals.iter().any(mentions_name)
+        }
+        Val::Field(ref val, _) | Val::Index(ref val, _) => mentions_name(val),
+        Val::String(_) | Val::Int(_) | Val::Unset => false,
+    }
+}
+
+/// Where `include`d files are found, and the chain of files being
+/// included, to detect cycles.
+struct Includes<'a> {
+    root: &'a Path,
+    dirs: &'a [PathBuf],
+    stack: RefCell<Vec<PathBuf>>,
+    /// The names `#define`d so far, in any file. Each file is
+    /// tokenized before the files it includes, so it only sees the
+    /// names they define after its own last line.
+    defines: RefCell<HashSet<String>>,
 }
-fn expect_ident(tok: Token) -> String {
-    match tok {
-        Token::Ident(s) => s,
-        _ => panic!("expected ident, found {:?}", tok)
+
+impl<'a> Includes<'a> {
+    /// Looks for `name` relative to the directory of the file including
+    /// it, then the root, then each of the include directories in
+    /// order, like `llvm-tblgen -I`. The path is canonicalized, so
+    /// that the same file reached by different paths is recognised.
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        let stack = self.stack.borrow();
+        let including = stack.last().and_then(|file| file.parent());
+        including.into_iter()
+            .chain(Some(self.root))
+            .chain(self.dirs.iter().map(|d| &**d))
+            .map(|dir| dir.join(name))
+            .find(|path| fs::metadata(path).map(|m| m.is_file()).unwrap_or(false))
+            .and_then(|path| fs::canonicalize(path).ok())
     }
 }
 
-impl<'a, I: Iterator<Item = Token>> Parser<'a, I> {
-    fn subparser<'b, J: Iterator<Item = Token>>(&'b self, iter: J) -> Parser<'b,J> {
+struct Parser<'a, I: Iterator<Item = (Token, Span)>> {
+    tokens: I,
+    includes: &'a Includes<'a>,
+    /// Where the last token read is, where errors are reported.
+    span: Span,
+}
+
+impl<'a, I: Iterator<Item = (Token, Span)>> Parser<'a, I> {
+    fn subparser<'b, J: Iterator<Item = (Token, Span)>>(&'b self, iter: J) -> Parser<'b,J> {
         Parser {
             tokens: iter,
-            root: self.root
+            includes: self.includes,
+            span: self.span.clone(),
+        }
+    }
+
+    fn error<T>(&self, message: String) -> ParseResult<T> {
+        Err(ParseError::new(self.span.clone(), message))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        match self.tokens.next() {
+            Some((tok, span)) => { self.span = span; Some(tok) }
+            None => None
+        }
+    }
+    fn token(&mut self) -> ParseResult<Token> {
+        match self.next() {
+            Some(tok) => Ok(tok),
+            None => self.error("unexpected end of input".to_string())
+        }
+    }
+    /// `tok`, a token already read, or else the next one.
+    fn take_or_eof(&mut self, tok: Option<Token>) -> Option<Token> {
+        match tok {
+            Some(tok) => Some(tok),
+            None => self.next()
+        }
+    }
+    fn take(&mut self, tok: Option<Token>) -> ParseResult<Token> {
+        match tok {
+            Some(tok) => Ok(tok),
+            None => self.token()
         }
     }
 
-    fn token(&mut self) -> Token { self.tokens.next().expect("found EOF") }
-    fn expect_token(&mut self, expected: Token) {
-        expect(self.token(), expected);
+    fn expect(&self, tok: Token, expected: Token) -> ParseResult<()> {
+        if tok == expected {
+            Ok(())
+        } else {
+            self.error(format!("expected {}, found {}", expected, tok))
+        }
+    }
+    fn expect_token(&mut self, expected: Token) -> ParseResult<()> {
+        let tok = try!(self.token());
+        self.expect(tok, expected)
     }
-    fn expect_ident_or_eof(&mut self) -> Option<String> {
-        self.tokens.next().map(expect_ident)
+    fn expect_keyword(&mut self, tok: Option<Token>, keyword: &str) -> ParseResult<()> {
+        let tok = try!(self.take(tok));
+        self.expect(tok, Token::Ident(keyword.to_string()))
     }
-    fn expect_ident(&mut self) -> String {
-        self.expect_ident_or_eof().expect("expected ident, found EOF")
+    fn ident(&self, tok: Token) -> ParseResult<String> {
+        match tok {
+            Token::Ident(s) => Ok(s),
+            tok => self.error(format!("expected identifier, found {}", tok))
+        }
+    }
+    fn expect_ident_or_eof(&mut self) -> ParseResult<Option<String>> {
+        match self.next() {
+            Some(tok) => self.ident(tok).map(Some),
+            None => Ok(None)
+        }
+    }
+    fn expect_ident(&mut self) -> ParseResult<String> {
+        let tok = try!(self.token());
+        self.ident(tok)
     }
 
-    fn parse_items_to_eof(&mut self) -> Vec<Item> {
+    fn parse_items_to_eof(&mut self) -> ParseResult<Vec<Item>> {
         let mut ret = vec![];
-        while let Some(item) = self.parse_item_or_eof() {
-            ret.push(item);
+        while let Some(keyword) = try!(self.expect_ident_or_eof()) {
+            if keyword == "else" {
+                match ret.last_mut() {
+                    Some(&mut Item::If(ref mut if_)) => if_.otherwise = try!(self.parse_scope()),
+                    _ => return self.error("`else` without `if`".to_string())
+                }
+                continue
+            }
+            ret.push(try!(self.parse_item(&keyword)));
+        }
+        Ok(ret)
+    }
+
+    fn parse_item(&mut self, keyword: &str) -> ParseResult<Item> {
+        Ok(match keyword {
+            "def" => Item::Def(try!(self.parse_def())),
+            "defm" => Item::Defm(try!(self.parse_def())),
+            "let" => Item::Let(try!(self.parse_let())),
+            "class" => Item::Class(try!(self.parse_class())),
+            "multiclass" => Item::Multiclass(try!(self.parse_multiclass())),
+            "include" => Item::Include(try!(self.parse_include())),
+            "foreach" => Item::Foreach(try!(self.parse_foreach())),
+            "defvar" => Item::Defvar(try!(self.parse_defvar())),
+            "defset" => Item::Defset(try!(self.parse_defset())),
+            "if" => Item::If(try!(self.parse_if())),
+            _ => return self.error(format!("unexpected keyword `{}`", keyword))
+        })
+    }
+
+    fn parse_def(&mut self) -> ParseResult<Def> {
+        let span = self.span.clone();
+        // the name is optional, and can be pasted together from
+        // several pieces, e.g. `NAME#"_x"`
+        let (name_parts, tok) = match try!(self.token()) {
+            tok @ Token::Colon | tok @ Token::Semicolon | tok @ Token::Braces(_) => (vec![], tok),
+            tok => {
+                let (name, next) = try!(self.parse_val(tok));
+                let parts = match name {
+                    Val::Paste(parts) => parts,
+                    name => vec![name]
+                };
+                (parts, try!(self.take(next)))
+            }
+        };
+
+        let (inherits, body) = match tok {
+            Token::Colon => try!(self.parse_inherits()),
+            Token::Braces(contents) => {
+                (vec![], try!(self.subparser(contents.into_iter()).parse_body()))
+            }
+            Token::Semicolon => (vec![], Body::default()),
+            tok => return self.error(format!("expected `:`, `;` or `{{...}}`, found {}", tok))
+        };
+        Ok(Def {
+            name: String::new(),
+            name_parts: name_parts,
+            inherits: inherits,
+            body: body,
+            lets: vec![],
+            fields: vec![],
+            span: span,
+        })
+    }
+
+    /// Parses `let A = a, B = b in { ... }` (or `in` a single item),
+    /// as a `Let` for each binding, nested in order.
+    fn parse_let(&mut self) -> ParseResult<Let> {
+        let mut bindings = vec![];
+        loop {
+            let name = try!(self.expect_ident());
+            try!(self.expect_token(Token::Equals));
+            let (value, tok) = try!(self.parse_next_val());
+            bindings.push((name, value));
+            match try!(self.take(tok)) {
+                Token::Comma => {}
+                Token::Ident(ref s) if s == "in" => break,
+                tok => return self.error(format!("expected `,` or `in`, found {}", tok))
+            }
+        }
+        let mut items = try!(self.parse_scope());
+        loop {
+            let (name, value) = bindings.pop().unwrap();
+            let let_ = Let { name: name, value: value, items: items };
+            if bindings.is_empty() { return Ok(let_) }
+            items = vec![Item::Let(let_)];
         }
-        ret
     }
 
-    fn parse_item_or_eof(&mut self) -> Option<Item> {
-        self.expect_ident_or_eof().map(|s| {
-            match &*s {
-                "def" => Item::Def(self.parse_def()),
-                "defm" => Item::Defm(self.parse_def()),
-                "let" => Item::Let(self.parse_let()),
-                "class" => Item::Class(self.parse_class()),
-                "multiclass" => Item::Multiclass(self.parse_class()),
-                "include" => Item::Include(self.parse_include()),
-                _ => panic!("unexpected keyword {}", s)
+    /// Parses `foreach x = [a, b, ...] in ...`, or a range of
+    /// integers, `foreach x = 0...3 in ...` or `0-3`.
+    fn parse_foreach(&mut self) -> ParseResult<Foreach> {
+        let var = try!(self.expect_ident());
+        try!(self.expect_token(Token::Equals));
+        let tok = try!(self.token());
+        let span = self.span.clone();
+        let (list, tok) = try!(self.parse_val(tok));
+        let list = match try!(self.take(tok)) {
+            Token::Ident(ref s) if s == "in" => list,
+            tok => {
+                let start = match list {
+                    Val::Int(n) => n,
+                    _ => return self.error(format!("expected `in`, found {}", tok))
+                };
+                // `a...b`, or `a-b`, which is lexed as `a` and `-b`
+                let end = match tok {
+                    Token::Dot => {
+                        try!(self.expect_token(Token::Dot));
+                        try!(self.expect_token(Token::Dot));
+                        match try!(self.token()) {
+                            Token::Int(n) => n,
+                            tok => return self.error(format!("expected integer, found {}", tok))
+                        }
+                    }
+                    Token::Int(n) if n <= 0 => -n,
+                    tok => return self.error(format!("expected `in`, found {}", tok))
+                };
+                try!(self.expect_keyword(None, "in"));
+                Val::List((start..end + 1).map(Val::Int).collect())
             }
+        };
+        Ok(Foreach {
+            var: var,
+            list: list,
+            items: try!(self.parse_scope()),
+            span: span,
+        })
+    }
+
+    /// Parses `if cond then ...`; the `else ...`, if any, is attached
+    /// by `parse_items_to_eof`.
+    fn parse_if(&mut self) -> ParseResult<If> {
+        let tok = try!(self.token());
+        let span = self.span.clone();
+        let (cond, tok) = try!(self.parse_val(tok));
+        try!(self.expect_keyword(tok, "then"));
+        Ok(If {
+            cond: cond,
+            then: try!(self.parse_scope()),
+            otherwise: vec![],
+            span: span,
         })
     }
 
-    fn parse_def(&mut self) -> Def {
-        let name = self.expect_ident();
-        self.expect_token(Token::Colon);
-        let inherits = self.parse_inherits();
-        Def { name: name, inherits: inherits }
+    fn parse_defvar(&mut self) -> ParseResult<Defvar> {
+        let name = try!(self.expect_ident());
+        try!(self.expect_token(Token::Equals));
+        let (value, tok) = try!(self.parse_next_val());
+        let tok = try!(self.take(tok));
+        try!(self.expect(tok, Token::Semicolon));
+        Ok(Defvar { name: name, value: value })
+    }
+
+    fn parse_defset(&mut self) -> ParseResult<Defset> {
+        // the type of the list only restricts the defs that can be in it
+        let (_ty, tok) = try!(self.parse_type(None));
+        let tok = try!(self.take(tok));
+        let name = try!(self.ident(tok));
+        try!(self.expect_token(Token::Equals));
+        let items = match try!(self.token()) {
+            Token::Braces(contents) => try!(self.subparser(contents.into_iter()).parse_items_to_eof()),
+            tok => return self.error(format!("expected `{{...}}`, found {}", tok))
+        };
+        Ok(Defset { name: name, items: items })
     }
 
-    fn parse_let(&mut self) -> Let {
-        let _name = self.expect_ident();
-        self.expect_token(Token::Equals);
-        match self.token() {
-            Token::Square(_) | Token::String(_) | Token::Int(_) => {}
-            tok => panic!("expected [...], string or int, found {:?}", tok)
+    /// Parses the items after the `in` of a `let` or `foreach`, or the
+    /// `then` or `else` of an `if`, either `{ ... }` or a single item.
+    fn parse_scope(&mut self) -> ParseResult<Vec<Item>> {
+        match try!(self.token()) {
+            Token::Braces(contents) => self.subparser(contents.into_iter()).parse_items_to_eof(),
+            Token::Ident(keyword) => Ok(vec![try!(self.parse_item(&keyword))]),
+            tok => self.error(format!("expected `{{...}}` or an item, found {}", tok))
         }
-        self.expect_token(Token::Ident("in".to_string()));
-        match self.token() {
+    }
+
+    fn parse_class(&mut self) -> ParseResult<Class> {
+        let name = try!(self.expect_ident());
+        let span = self.span.clone();
+        let (args, tok) = try!(self.parse_template_args());
+        let (inherits, body) = match tok {
+            Token::Semicolon => (vec![], Body::default()),
             Token::Braces(contents) => {
-                let mut subparser = self.subparser(contents.into_iter());
-                let items = subparser.parse_items_to_eof();
-                Let { items: items }
+                (vec![], try!(self.subparser(contents.into_iter()).parse_body()))
             }
-            tok => panic!("expected {{...}}, found {:?}", tok)
-        }
+            Token::Colon => try!(self.parse_inherits()),
+            tok => return self.error(format!("expected `:`, `;` or `{{...}}`, found {}", tok))
+        };
+        Ok(Class {
+            name: name,
+            args: args,
+            inherits: inherits,
+            body: body,
+            lets: vec![],
+            span: span,
+        })
+    }
+
+    fn parse_multiclass(&mut self) -> ParseResult<Multiclass> {
+        let name = try!(self.expect_ident());
+        let span = self.span.clone();
+        let (args, tok) = try!(self.parse_template_args());
+        let (inherits, tok) = match tok {
+            Token::Colon => try!(self.parse_superclasses()),
+            tok => (vec![], tok)
+        };
+        let items = match tok {
+            Token::Braces(contents) => {
+                try!(self.subparser(contents.into_iter()).parse_items_to_eof())
+            }
+            Token::Semicolon => vec![],
+            tok => return self.error(format!("expected `;` or `{{...}}`, found {}", tok))
+        };
+        Ok(Multiclass {
+            name: name,
+            args: args,
+            inherits: inherits,
+            items: items,
+            span: span,
+        })
     }
 
-    fn parse_class(&mut self) -> Class {
-        let name = self.expect_ident();
-        let (args, tok) = match self.token() {
+    /// Parses the `<...>` template arguments of a class or
+    /// multiclass, if there are any, returning the token after them.
+    fn parse_template_args(&mut self) -> ParseResult<(Vec<(Type, String, Option<Val>)>, Token)> {
+        match try!(self.token()) {
             Token::Spikey(contents) => {
                 let mut args = vec![];
                 {
                     let mut subparser = self.subparser(contents.into_iter());
-                    while let Some((ty, tok)) = subparser.try_parse_type_or_eof(None) {
-                        let next = tok.unwrap_or_else(|| subparser.token());
-                        let name = expect_ident(next);
-                        let (next, val) = match subparser.tokens.next() {
+                    while let Some((ty, tok)) = try!(subparser.try_parse_type_or_eof(None)) {
+                        let next = try!(subparser.take(tok));
+                        let name = try!(subparser.ident(next));
+                        let (next, val) = match subparser.next() {
                             Some(Token::Equals) => {
-                                let (val, tok) = subparser.try_parse_val_or_eof()
-                                    .expect("expected val, found EOF");
-
-                                (tok.or_else(|| subparser.tokens.next()), Some(val))
+                                let (val, tok) = try!(subparser.parse_next_val());
+                                (subparser.take_or_eof(tok), Some(val))
                             }
                             tok => (tok, None),
                         };
                         args.push((ty, name, val));
                         match next {
-                            Some(tok) => expect(tok, Token::Comma),
+                            Some(tok) => try!(subparser.expect(tok, Token::Comma)),
                             None => break
                         }
                     }
                 }
-                (args, self.token())
+                Ok((args, try!(self.token())))
             }
-            tok => (vec![], tok),
-        };
-        let inherits = match tok {
-            Token::Semicolon | Token::Braces(_) => vec![],
-            Token::Colon => self.parse_inherits(),
-            tok => panic!("expected : ; or {{...}}, found {:?}", tok)
-        };
-        Class {
-            name: name,
-            args: args,
-            inherits: inherits
+            tok => Ok((vec![], tok)),
         }
     }
 
-    fn parse_include(&mut self) -> Include {
-        let path = match self.token() {
+    fn parse_include(&mut self) -> ParseResult<Include> {
+        let path = match try!(self.token()) {
             Token::String(s) => s,
-            tok => panic!("expected string, found {:?}", tok)
+            tok => return self.error(format!("expected string, found {}", tok))
         };
 
-        if true {
-            // ignore includes for now
-            return Include { items: vec![] }
+        let file = match self.includes.find(&path) {
+            Some(file) => file,
+            None => return self.error(format!("could not find include file `{}`", path))
+        };
+        if self.includes.stack.borrow().contains(&file) {
+            let chain = self.includes.stack.borrow().iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>();
+            return self.error(format!("include cycle: {} -> {}",
+                                      chain.connect(" -> "), file.display()))
         }
-        let file = self.root.join(path);
+
         let mut s = String::new();
-        File::open(&file).unwrap().read_to_string(&mut s).unwrap();
-        Include { items: parse(&s, self.root) }
+        if let Err(e) = File::open(&file).and_then(|mut f| f.read_to_string(&mut s)) {
+            return self.error(format!("could not read `{}`: {}", file.display(), e))
+        }
+        let source = Rc::new(Source { name: file.display().to_string(), text: s });
+        let tokens = try!(tokenize(&source, &mut self.includes.defines.borrow_mut()));
+
+        self.includes.stack.borrow_mut().push(file);
+        let items = {
+            let mut subparser = self.subparser(tokens.into_iter());
+            subparser.span = Span { source: source, line: 1, col: 1 };
+            subparser.parse_items_to_eof()
+        };
+        self.includes.stack.borrow_mut().pop();
+        Ok(Include { items: try!(items) })
+    }
+
+    /// Parses the superclasses after the `:` of a class or def, and
+    /// the body, if any.
+    fn parse_inherits(&mut self) -> ParseResult<(Vec<Type>, Body)> {
+        match try!(self.parse_superclasses()) {
+            (ret, Token::Braces(contents)) => {
+                Ok((ret, try!(self.subparser(contents.into_iter()).parse_body())))
+            }
+            (ret, _) => Ok((ret, Body::default())),
+        }
     }
 
-    fn parse_inherits(&mut self) -> Vec<Type> {
+    /// Parses a list of superclasses, up to the `;` or `{...}` after
+    /// them, which is returned.
+    fn parse_superclasses(&mut self) -> ParseResult<(Vec<Type>, Token)> {
         let mut ret = vec![];
 
         loop {
-            let (ty, tok) = self.try_parse_type_or_eof(None).expect("expected type, found EOF");
+            let (ty, tok) = try!(self.parse_type(None));
             ret.push(ty);
 
-            match tok.unwrap_or_else(|| self.token()) {
+            match try!(self.take(tok)) {
                 Token::Comma => {},
-                Token::Semicolon | Token::Braces(_) => break,
-                tok => panic!("expected , ; or {{...}}, found {:?}", tok)
+                tok @ Token::Semicolon | tok @ Token::Braces(_) => return Ok((ret, tok)),
+                tok => return self.error(format!("expected `,`, `;` or `{{...}}`, found {}", tok))
             }
         }
-        ret
     }
 
-    fn try_parse_type_or_eof(&mut self, first: Option<Token>) -> Option<(Type, Option<Token>)> {
-        first.map(expect_ident).or_else(|| self.expect_ident_or_eof()).map(|name| {
-            match self.tokens.next() {
-                Some(Token::Spikey(contents)) => {
-                    let mut subparser = self.subparser(contents.into_iter());
-                    let vals = subparser.parse_vals_until_eof();
-                    (Type {
-                        name: name,
-                        args: vals,
-                    }, None)
+    /// Parses the contents of a `{ ... }` body.
+    fn parse_body(&mut self) -> ParseResult<Body> {
+        let mut ret = Body::default();
+        while let Some(tok) = self.next() {
+            let (name, tok) = match tok {
+                Token::Semicolon => continue,
+                Token::Ident(ref s) if s == "let" => (try!(self.expect_ident()), try!(self.token())),
+                Token::Ident(ref s) if s == "defvar" => {
+                    let Defvar { name, value } = try!(self.parse_defvar());
+                    ret.vars.push((name, value));
+                    continue
+                }
+                tok => {
+                    // a declaration, `[field] type name [= value]`
+                    let tok = match tok {
+                        Token::Ident(ref s) if s == "field" => try!(self.token()),
+                        tok => tok
+                    };
+                    let (_ty, next) = try!(self.parse_type(Some(tok)));
+                    let next = try!(self.take(next));
+                    (try!(self.ident(next)), try!(self.token()))
+                }
+            };
+            let val = match tok {
+                Token::Semicolon => None,
+                Token::Equals => {
+                    let (val, tok) = try!(self.parse_next_val());
+                    let tok = try!(self.take(tok));
+                    try!(self.expect(tok, Token::Semicolon));
+                    Some(val)
                 }
-                tok => (Type { name: name, args: vec![] }, tok),
+                tok => return self.error(format!("expected `;` or `=`, found {}", tok))
+            };
+            ret.fields.push((name, val));
+        }
+        Ok(ret)
+    }
+
+    fn try_parse_type_or_eof(&mut self, first: Option<Token>)
+                             -> ParseResult<Option<(Type, Option<Token>)>> {
+        let name = match self.take_or_eof(first) {
+            Some(tok) => try!(self.ident(tok)),
+            None => return Ok(None)
+        };
+        Ok(Some(match self.next() {
+            Some(Token::Spikey(contents)) => {
+                let mut subparser = self.subparser(contents.into_iter());
+                let vals = try!(subparser.parse_vals_until_eof());
+                (Type {
+                    name: name,
+                    args: vals,
+                }, None)
             }
-        })
+            tok => (Type { name: name, args: vec![] }, tok),
+        }))
     }
-    fn parse_vals_until_eof(&mut self) -> Vec<Val> {
+    fn parse_type(&mut self, first: Option<Token>) -> ParseResult<(Type, Option<Token>)> {
+        match try!(self.try_parse_type_or_eof(first)) {
+            Some(ty) => Ok(ty),
+            None => self.error("expected type, found end of input".to_string())
+        }
+    }
+    fn parse_vals_until_eof(&mut self) -> ParseResult<Vec<Val>> {
         let mut ret = vec![];
-        while let Some((val, tok)) = self.try_parse_val_or_eof() {
+        while let Some((val, tok)) = try!(self.try_parse_val_or_eof()) {
             ret.push(val);
-            match tok.or_else(|| self.tokens.next()) {
-                Some(tok) => expect(tok, Token::Comma),
+            match self.take_or_eof(tok) {
+                Some(tok) => try!(self.expect(tok, Token::Comma)),
                 None => break
             }
         }
-        ret
+        Ok(ret)
     }
-    fn try_parse_val_or_eof(&mut self) -> Option<(Val, Option<Token>)> {
-        self.tokens.next().map(|tok| {
-            match tok {
-                Token::Square(contents) => {
-                    let mut subparser = self.subparser(contents.into_iter());
-                    let vals = subparser.parse_vals_until_eof();
-                    (Val::List(vals), None)
-                }
-                Token::Int(n) => {
-                    (Val::Int(n), None)
-                }
-                Token::String(s) => {
-                    (Val::String(s), None)
-                }
-                Token::Bang => {
-                    self.expect_token(Token::Ident("strconcat".to_string()));
-                    match self.token() {
-                        Token::Parens(contents) => {
-                            let mut subparser = self.subparser(contents.into_iter());
-                            let vals = subparser.parse_vals_until_eof();
-                            (Val::Strconcat(vals), None)
-                        }
-                        tok => panic!("expected (...), found {:?}", tok)
+    fn try_parse_val_or_eof(&mut self) -> ParseResult<Option<(Val, Option<Token>)>> {
+        match self.next() {
+            Some(tok) => self.parse_val(tok).map(Some),
+            None => Ok(None)
+        }
+    }
+    fn parse_next_val(&mut self) -> ParseResult<(Val, Option<Token>)> {
+        let tok = try!(self.token());
+        self.parse_val(tok)
+    }
+    /// Parses a value starting with `tok`, and the `# ...` pasted
+    /// onto it.
+    fn parse_val(&mut self, tok: Token) -> ParseResult<(Val, Option<Token>)> {
+        let (val, mut tok) = try!(self.parse_single_val(tok));
+        let mut parts = vec![val];
+        loop {
+            match self.take_or_eof(tok) {
+                Some(Token::Paste) => {
+                    let next = try!(self.token());
+                    let (val, next) = try!(self.parse_single_val(next));
+                    parts.push(val);
+                    tok = next;
+                }
+                next if parts.len() == 1 => return Ok((parts.pop().unwrap(), next)),
+                next => return Ok((Val::Paste(parts), next)),
+            }
+        }
+    }
+    fn parse_single_val(&mut self, tok: Token) -> ParseResult<(Val, Option<Token>)> {
+        let (mut val, mut tok) = match tok {
+            Token::Square(contents) => {
+                let vals = try!(self.subparser(contents.into_iter()).parse_vals_until_eof());
+                // the element type of an empty list, `[]<T>`
+                match self.next() {
+                    Some(Token::Spikey(_)) => (Val::List(vals), None),
+                    tok => (Val::List(vals), tok),
+                }
+            }
+            Token::Int(n) => return Ok((Val::Int(n), None)),
+            Token::Ident(ref s) if s == "true" => return Ok((Val::Int(1), None)),
+            Token::Ident(ref s) if s == "false" => return Ok((Val::Int(0), None)),
+            Token::String(mut s) => {
+                // adjacent strings are concatenated
+                let mut next = self.next();
+                loop {
+                    match next {
+                        Some(Token::String(ref t)) => s.push_str(t),
+                        _ => break
                     }
+                    next = self.next();
                 }
-                _ => {
-                    let (ty, tok) = self.try_parse_type_or_eof(Some(tok))
-                        .expect("expected type, found EOF");
-                    (Val::Type(ty), tok)
+                (Val::String(s), next)
+            }
+            Token::Code(s) => (Val::String(s), None),
+            Token::Braces(contents) => {
+                let bits = try!(self.subparser(contents.into_iter()).parse_vals_until_eof());
+                (Val::Bits(bits), None)
+            }
+            Token::Question => (Val::Unset, None),
+            Token::Bang => {
+                let span = self.span.clone();
+                let op = try!(self.expect_ident());
+                let (ty, tok) = match try!(self.token()) {
+                    Token::Spikey(contents) => {
+                        let mut subparser = self.subparser(contents.into_iter());
+                        let (ty, _) = try!(subparser.parse_type(None));
+                        (Some(ty), try!(self.token()))
+                    }
+                    tok => (None, tok)
+                };
+                let contents = match tok {
+                    Token::Parens(contents) => contents,
+                    tok => return self.error(format!("expected `(...)`, found {}", tok))
+                };
+                let mut subparser = self.subparser(contents.into_iter());
+                let mut args = vec![];
+                while let Some((val, tok)) = try!(subparser.try_parse_val_or_eof()) {
+                    args.push(val);
+                    match subparser.take_or_eof(tok) {
+                        // `!cond(a: x, b: y)`
+                        Some(Token::Colon) if op == "cond" => {}
+                        Some(tok) => try!(subparser.expect(tok, Token::Comma)),
+                        None => break
+                    }
+                }
+                for &i in bound_vars(&op, args.len()).0.iter() {
+                    match args[i] {
+                        Val::Type(ref ty) if ty.args.is_empty() => {}
+                        ref v => return Err(ParseError::new(span, format!(
+                            "expected a variable name as argument {} of `!{}`, found {:?}",
+                            i + 1, op, v)))
+                    }
                 }
+                (Val::Bang(op, ty, args), None)
+            }
+            tok @ Token::Ident(_) => {
+                let (ty, tok) = try!(self.parse_type(Some(tok)));
+                (Val::Type(ty), tok)
             }
+            tok => return self.error(format!("expected value, found {}", tok))
+        };
+        loop {
+            match self.take_or_eof(tok) {
+                Some(Token::Dot) => {
+                    val = Val::Field(Box::new(val), try!(self.expect_ident()));
+                    tok = None
+                }
+                Some(Token::Square(contents)) => {
+                    let index = try!(self.subparser(contents.into_iter()).parse_vals_until_eof());
+                    let n = match index.first() {
+                        Some(&Val::Int(n)) if index.len() == 1 && n >= 0 => n as u32,
+                        _ => return self.error("only a single integer index is supported".to_string())
+                    };
+                    val = Val::Index(Box::new(val), n);
+                    tok = None
+                }
+                next => return Ok((val, next))
+            }
+        }
+    }
+}
+
+/// Parses the TableGen source `s`, resolving `include`s relative to
+/// the including file, `root` and then `include_dirs`, with `defines`
+/// `#define`d.
+pub fn parse(s: &str, root: &Path, include_dirs: &[PathBuf],
+             defines: &[String]) -> ParseResult<Vec<Item>> {
+    let source = Source { name: "<stdin>".to_string(), text: s.to_string() };
+    parse_with_stack(source, root, include_dirs, defines, vec![])
+}
+
+/// Parses the TableGen file at `path`, resolving `include`s relative
+/// to the including file, its directory and then `include_dirs`, with
+/// `defines` `#define`d.
+pub fn parse_file(path: &Path, include_dirs: &[PathBuf],
+                  defines: &[String]) -> ParseResult<Vec<Item>> {
+    let mut s = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        return Err(ParseError {
+            span: None,
+            message: format!("could not read `{}`: {}", path.display(), e),
         })
     }
+    let source = Source { name: path.display().to_string(), text: s };
+    let root = path.parent().unwrap_or(Path::new(""));
+    let file = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    parse_with_stack(source, root, include_dirs, defines, vec![file])
 }
 
-pub fn parse(s: &str, root: &Path) -> Vec<Item> {
+fn parse_with_stack(source: Source, root: &Path, include_dirs: &[PathBuf],
+                    defines: &[String], stack: Vec<PathBuf>) -> ParseResult<Vec<Item>> {
+    let includes = Includes {
+        root: root,
+        dirs: include_dirs,
+        stack: RefCell::new(stack),
+        defines: RefCell::new(defines.iter().cloned().collect()),
+    };
+    let source = Rc::new(source);
+    let tokens = try!(tokenize(&source, &mut includes.defines.borrow_mut()));
     let mut p = Parser {
-        tokens: tokenize(s).into_iter(),
-        root: root
+        tokens: tokens.into_iter(),
+        includes: &includes,
+        span: Span { source: source.clone(), line: 1, col: 1 },
     };
     p.parse_items_to_eof()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::path::{Path, PathBuf};
+    use super::{parse, parse_file, evaluate, Def, Val};
+
+    fn defs(s: &str) -> Vec<Def> {
+        evaluate(&parse(s, Path::new(""), &[], &[]).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fields() {
+        let defs = defs(r#"
+class A<int x> { int X = x; int Y = 1; int Z = 1; string S; }
+class B<int y> : A<y> { let Y = 2; }
+let Z = 3 in {
+  def d : B<4> { let S = "s"; }
+  let Y = 5 in def e : B<6> { let Z = 7; }
+}
+"#);
+        assert_eq!(defs[0].field("X"), Some(&Val::Int(4)));
+        // the class body overrides its superclass, and the `let`s
+        // override both
+        assert_eq!(defs[0].field("Y"), Some(&Val::Int(2)));
+        assert_eq!(defs[0].field("Z"), Some(&Val::Int(3)));
+        assert_eq!(defs[0].field("S"), Some(&Val::String("s".to_string())));
+
+        assert_eq!(defs[1].field("X"), Some(&Val::Int(6)));
+        assert_eq!(defs[1].field("Y"), Some(&Val::Int(5)));
+        // the def's body overrides the `let`s
+        assert_eq!(defs[1].field("Z"), Some(&Val::Int(7)));
+        assert_eq!(defs[1].field("S"), Some(&Val::Unset));
+    }
+
+    #[test]
+    fn multiclasses() {
+        let defs = defs(r#"
+class I<string n, list<int> l> { string N = n; list<int> L = l; }
+class Extra;
+multiclass M<string s, list<int> l = [1]> {
+  def _a : I<s, l>;
+  def NAME#"_b" : I<s # "b", l # [2]>;
+}
+multiclass N<string t> : M<t, [3]> {
+  defm NAME : M<t>;
+  let Z = 1 in defm _c : M<"c">;
+}
+defm int_foo : N<"x">, Extra;
+"#);
+        let names = defs.iter().map(|d| &*d.name).collect::<Vec<_>>();
+        assert_eq!(names, ["int_foo_a", "int_foo_b", "int_foo_a", "int_foo_b",
+                           "int_foo_c_a", "int_foo_c_b"]);
+        assert_eq!(defs[0].field("L"), Some(&Val::List(vec![Val::Int(3)])));
+        assert_eq!(defs[1].field("N"), Some(&Val::String("xb".to_string())));
+        assert_eq!(defs[3].field("L"), Some(&Val::List(vec![Val::Int(1), Val::Int(2)])));
+        assert_eq!(defs[4].lets, [("Z".to_string(), Val::Int(1))]);
+        assert!(defs.iter().all(|d| d.inherits.iter().any(|t| t.name == "Extra")));
+    }
+
+    #[test]
+    fn foreach_defvar_defset() {
+        let defs = defs(r#"
+class I<int n> { int N = n; }
+defvar base = 10;
+defset list<I> Set = {
+  foreach i = [1, 2] in def int_a # i : I<!add(base, i)>;
+  foreach i = 3...4 in {
+    if !eq(i, 4) then def int_b # i : I<i>;
+  }
+}
+foreach d = Set in def d # _x : I<!size(Set)>;
+"#);
+        let names = defs.iter().map(|d| &*d.name).collect::<Vec<_>>();
+        assert_eq!(names, ["int_a1", "int_a2", "int_b4", "int_a1_x", "int_a2_x", "int_b4_x"]);
+        assert_eq!(defs[1].field("N"), Some(&Val::Int(12)));
+        assert_eq!(defs[2].field("N"), Some(&Val::Int(4)));
+        assert_eq!(defs[3].field("N"), Some(&Val::Int(3)));
+    }
+
+    #[test]
+    fn body_defvars() {
+        let defs = defs(r#"
+class T<int n> { int N = n; }
+def t0 : T<0>;
+class A<int n> {
+  defvar a = !add(n, 1);
+  defvar b = !mul(a, 2);
+  int X = b;
+}
+multiclass M<int m> {
+  def _x : A<m> {
+    defvar isT = !isa<T>(t0);
+    defvar c = !add(m, t0.N, isT);
+    int Y = c;
+  }
+}
+defm int_m : M<3>;
+"#);
+        let d = &defs[1];
+        assert_eq!(d.name, "int_m_x");
+        assert_eq!(d.field("X"), Some(&Val::Int(8)));
+        assert_eq!(d.field("Y"), Some(&Val::Int(4)));
+    }
+
+    #[test]
+    fn bang_operators() {
+        let defs = defs(r#"
+class T<int n> { int Number = n; }
+class Arg<T t, string name> { T Type = t; string Name = name; }
+class Shift<list<Arg> args, int by> {
+  list<Arg> ret = !foreach(a, args, Arg<T<!add(a.Type.Number, by)>, a.Name>);
+}
+def t0 : T<0>;
+class D<list<string> names> {
+  defvar first = names[0];
+  list<Arg> Args = Shift<[Arg<t0, first>, Arg<T<1>, "y">], 2>.ret;
+  list<string> Names = !foreach(a, Args, a.Name);
+  list<int> Numbers = !foreach(a, Args, a.Type.Number);
+  int Sum = !foldl(0, Numbers, acc, n, !add(acc, n));
+  list<int> Big = !filter(n, Numbers, !gt(n, 2));
+  string S = !if(!empty(names), "none", !strconcat(first, "_", !cast<string>(!size(names))));
+  list<int> L = !listconcat([1], !listsplat(0, 2), !tail([5, 6]));
+  string Sub = !subst("int", "__builtin", NAME);
+  bit IsT = !isa<T>(t0);
+  bit NotEq = !ne(first, "x");
+  string C = !cond(!eq(Sum, 0): "zero", true: "nonzero");
+}
+def int_d : D<["x", "z"]>;
+"#);
+        let d = &defs[1];
+        let strings = |v: &[&str]| Val::List(v.iter().map(|s| Val::String(s.to_string())).collect());
+        let ints = |v: &[i64]| Val::List(v.iter().map(|&n| Val::Int(n)).collect());
+        assert_eq!(d.field("Names"), Some(&strings(&["x", "y"])));
+        assert_eq!(d.field("Numbers"), Some(&ints(&[2, 3])));
+        assert_eq!(d.field("Sum"), Some(&Val::Int(5)));
+        assert_eq!(d.field("Big"), Some(&ints(&[3])));
+        assert_eq!(d.field("S"), Some(&Val::String("x_2".to_string())));
+        assert_eq!(d.field("L"), Some(&ints(&[1, 0, 0, 6])));
+        assert_eq!(d.field("Sub"), Some(&Val::String("__builtin_d".to_string())));
+        assert_eq!(d.field("IsT"), Some(&Val::Int(1)));
+        assert_eq!(d.field("NotEq"), Some(&Val::Int(0)));
+        assert_eq!(d.field("C"), Some(&Val::String("nonzero".to_string())));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |s: &str| parse(s, Path::new(""), &[], &[]).err().unwrap();
+
+        let e = error("class A;\ndef x : A {\n  let y = ;\n}\n");
+        let span = e.span.clone().unwrap();
+        assert_eq!((span.line, span.col), (3, 11));
+        assert_eq!(e.message, "expected value, found `;`");
+        assert_eq!(e.to_string(),
+                   "<stdin>:3:11: expected value, found `;`\n      let y = ;\n              ^");
+
+        let e = error("def x : A<1 };");
+        assert_eq!(e.message, "expected `>` to close the `<` at 1:10, found `}`");
+
+        let e = error("def x : A;\n\"abc");
+        let span = e.span.unwrap();
+        assert_eq!((span.line, span.col), (2, 1));
+        assert_eq!(e.message, "unterminated string");
+
+        let e = error("#ifdef FOO\ndef x;\n");
+        assert_eq!(e.message, "unterminated `#ifdef`");
+
+        let e = error("def x {\n  string S = \"\\q\";\n}");
+        let span = e.span.unwrap();
+        assert_eq!((span.line, span.col), (2, 14));
+        assert_eq!(e.message, "unknown escape `\\q`");
+
+        let e = error("include \"missing.td\"");
+        assert_eq!(e.message, "could not find include file `missing.td`");
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let error = |s: &str| {
+            let e = parse(s, Path::new(""), &[], &[]).and_then(|items| evaluate(&items))
+                .err().unwrap();
+            let span = e.span.unwrap();
+            (span.line, span.col, e.message)
+        };
+
+        assert_eq!(error("class A;\nclass A;"),
+                   (2, 7, "class `A` is defined twice".to_string()));
+        assert_eq!(error("foreach x = 1 in def d;"),
+                   (1, 13, "`foreach x` over Int(1), which isn't a list".to_string()));
+        assert_eq!(error("if \"s\" then def d;"),
+                   (1, 4, "`if` on String(\"s\"), which isn't a bit".to_string()));
+        assert_eq!(error("class A<int n>;\ndef d : A;"),
+                   (2, 1, "missing argument `n` to `A`".to_string()));
+        // at the `defm`, or the multiclass for its own superclasses
+        assert_eq!(error("multiclass M<int n> { def _a; }\ndefm d : M;"),
+                   (2, 1, "missing argument `n` to `M`".to_string()));
+        assert_eq!(error("multiclass M : N { def _a; }\ndefm d : M;"),
+                   (1, 12, "`M` inherits from `N`, which isn't a multiclass".to_string()));
+        assert_eq!(error("class A;\ndef x#[1] : A;"),
+                   (2, 1, "invalid piece of the name of `x`: List([Int(1)])".to_string()));
+        assert_eq!(error("def d { list<int> L = !foreach(1, [1], 2); }"),
+                   (1, 23, "expected a variable name as argument 1 of `!foreach`, found Int(1)"
+                           .to_string()));
+        assert_eq!(error("multiclass M { def _a; }\ndefm d : M, N;"),
+                   (2, 1, "unknown multiclass or class `N`".to_string()));
+        assert_eq!(error("class A;\ndefm d : A;"),
+                   (2, 1, "`defm d` doesn't inherit from a multiclass".to_string()));
+    }
+
+    #[test]
+    fn anonymous_defs() {
+        let defs = defs("class A<int n> { int N = n; }\ndef : A<1>;\ndef : A<2>;");
+        let names = defs.iter().map(|d| &d.name[..]).collect::<Vec<_>>();
+        assert_eq!(names, ["anonymous_0", "anonymous_1"]);
+        assert_eq!(defs[1].field("N"), Some(&Val::Int(2)));
+    }
+
+    #[test]
+    fn lexical_syntax() {
+        let defs = defs(r#"
+/* a /* nested */
+   comment */
+#define FOO
+#ifdef FOO
+class A<int n> { int N = n; }
+#else
+class A<int n> { int N = 0; }
+#endif
+#ifndef FOO
+def skipped;
+#endif
+def x : A<-16> {
+  string S = "a\"b\\c\t" "d";
+  code C = [{ return x; }];
+  int H = 0x1F;
+  int B = 0b101;
+  bits<3> Bits = {1, 0, 1};
+}
+"#);
+        assert_eq!(defs.len(), 1);
+        let d = &defs[0];
+        assert_eq!(d.field("N"), Some(&Val::Int(-16)));
+        assert_eq!(d.field("S"), Some(&Val::String("a\"b\\c\td".to_string())));
+        assert_eq!(d.field("C"), Some(&Val::String(" return x; ".to_string())));
+        assert_eq!(d.field("H"), Some(&Val::Int(31)));
+        assert_eq!(d.field("B"), Some(&Val::Int(5)));
+        assert_eq!(d.field("Bits"), Some(&Val::Int(5)));
+
+        let items = parse("#ifdef BAR\ndef y;\n#endif\n", Path::new(""), &[],
+                          &["BAR".to_string()]).unwrap();
+        assert_eq!(evaluate(&items).unwrap().len(), 1);
+    }
+
+    /// Writes each `(path, contents)` under a fresh temporary
+    /// directory, which is returned.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("generator-test-{}", test));
+        let _ = fs::remove_dir_all(&dir);
+        for &(path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn include_resolution() {
+        let dir = write_files("include-resolution", &[
+            ("top.td", "include \"sub/a.td\"\ndef top;\n"),
+            // relative to `sub`, not the root
+            ("sub/a.td", "include \"b.td\"\ndef a;\n"),
+            ("sub/b.td", "include \"c.td\"\ndef b;\n"),
+            ("b.td", "def wrong_b;\n"),
+            ("inc/c.td", "def c;\n"),
+        ]);
+        let items = parse_file(&dir.join("top.td"), &[dir.join("inc")], &[]).unwrap();
+        let names = evaluate(&items).unwrap().into_iter().map(|d| d.name).collect::<Vec<_>>();
+        assert_eq!(names, ["c", "b", "a", "top"]);
+
+        // the root is searched when reading from stdin
+        let items = parse("include \"b.td\"", &dir, &[], &[]).unwrap();
+        assert_eq!(evaluate(&items).unwrap()[0].name, "wrong_b");
+    }
+
+    #[test]
+    fn include_cycle() {
+        let dir = write_files("include-cycle", &[
+            ("x.td", "include \"./sub/y.td\"\n"),
+            ("sub/y.td", "include \"../x.td\"\n"),
+        ]);
+        let e = parse_file(&dir.join("x.td"), &[], &[]).err().unwrap();
+        let x = fs::canonicalize(dir.join("x.td")).unwrap();
+        let y = fs::canonicalize(dir.join("sub/y.td")).unwrap();
+        assert_eq!(e.message, format!("include cycle: {} -> {} -> {}",
+                                      x.display(), y.display(), x.display()));
+        let span = e.span.unwrap();
+        assert_eq!((&*span.source.name, span.line, span.col),
+                   (&*y.display().to_string(), 1, 9));
+    }
+}
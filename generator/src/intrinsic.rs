@@ -12,8 +12,12 @@ pub enum MatchStyle {
 pub enum LLVMType {
     Int(Option<u32>),
     Float(Option<u32>),
+    /// LLVM's `bfloat`, which has the same width as `half`.
+    BFloat,
     FixedPoint(u32),
-    Ptr(Option<Box<LLVMType>>),
+    /// A pointer to the type in an address space (0 being the
+    /// default one), or in any of them if `None`.
+    Ptr(Box<LLVMType>, Option<u32>),
 
     Vector(Option<(u32, Box<LLVMType>)>),
     Metadata,
@@ -23,28 +27,77 @@ pub enum LLVMType {
     Mips(Box<LLVMType>),
 
     MatchedType(u32, MatchStyle),
+    /// `LLVMScalarOrSameVectorWidth<n, ty>`: `ty` if the overloaded
+    /// type `n` is a scalar, otherwise a vector of `ty` with as many
+    /// elements as it.
+    SameWidth(u32, Box<LLVMType>),
 }
 
 
+/// The total sizes (in bits) of the vectors that overloaded
+/// intrinsics are instantiated at: NEON `d` registers, SSE/NEON `q`
+/// registers, AVX, AVX-512 and 128-byte HVX vectors.
+pub const DEFAULT_VECTOR_WIDTHS: &'static [u32] = &[64, 128, 256, 512, 1024];
+
+/// The total sizes of the vectors that an architecture's intrinsics
+/// work on, by architecture: overloaded vectors are only instantiated
+/// at the requested widths that are in this list.
+fn vector_widths(arch: Option<Arch>) -> &'static [u32] {
+    // MMX, SSE, AVX and AVX-512
+    const ALL: &'static [u32] = &[64, 128, 256, 512];
+    // NEON's `d` and `q` registers, and small GPU vectors
+    const NARROW: &'static [u32] = &[64, 128];
+    // HVX's 64- and 128-byte vectors; the pairs of them that some
+    // intrinsics take, of up to 2048 bits, are concrete types
+    const HVX: &'static [u32] = &[512, 1024];
+    const W128: &'static [u32] = &[128];
+    const NONE: &'static [u32] = &[];
+    match arch {
+        None | Some(Arch::X86) => ALL,
+        Some(Arch::Aarch64) | Some(Arch::Arm) |
+        Some(Arch::Cuda) | Some(Arch::Nvvm) | Some(Arch::Ptx) |
+        Some(Arch::AMDGPU) | Some(Arch::Amdgcn) | Some(Arch::R600) => NARROW,
+        Some(Arch::Hexagon) => HVX,
+        // the RISC-V and VE vector intrinsics are overloaded over
+        // scalable and 256-element vectors, which Rust can't express
+        Some(Arch::Riscv) | Some(Arch::Ve) => NONE,
+        Some(_) => W128,
+    }
+}
+
+/// Whether `simdty` has a vector type with `n` elements.
+fn simdty_has_lanes(n: u32) -> bool {
+    n.count_ones() == 1 && 2 <= n && n <= 64
+}
+
 enum TypeKind {
     Generic, Matched(u32), Concrete,
 }
 
 fn int(x: u32) -> LLVMType { LLVMType::Int(Some(x)) }
 fn float(x: u32) -> LLVMType { LLVMType::Float(Some(x)) }
-fn ptr(ty: LLVMType) -> LLVMType { LLVMType::Ptr(Some(Box::new(ty))) }
+fn ptr(ty: LLVMType) -> LLVMType { ptr_in(0, ty) }
+fn ptr_in(space: u32, ty: LLVMType) -> LLVMType { LLVMType::Ptr(Box::new(ty), Some(space)) }
+fn any_ptr(ty: LLVMType) -> LLVMType { LLVMType::Ptr(Box::new(ty), None) }
 
 fn parse_internals(s: &str) -> Result<LLVMType, ()> {
     match s {
         "float" => return Ok(float(32)),
         "double" => return Ok(float(64)),
+        "half" => return Ok(float(16)),
+        "bfloat" | "bf16" => return Ok(LLVMType::BFloat),
         "anyvector" => return Ok(LLVMType::Vector(None)),
         "anyfloat" => return Ok(LLVMType::Float(None)),
         "anyint" => return Ok(LLVMType::Int(None)),
-        "anyptr" => return Ok(LLVMType::Ptr(None)),
+        "anyptr" => return Ok(any_ptr(int(8))),
         "ptr" => return Ok(ptr(int(8))),
         "ptrptr" => return Ok(ptr(ptr(int(8)))),
-        "anyi64ptr" => return Ok(ptr(int(64))),
+        "anyi64ptr" | "any_i64ptr" => return Ok(any_ptr(int(64))),
+        // NVVM's pointers
+        "i64ptr" => return Ok(ptr(int(64))),
+        "global_i8ptr" => return Ok(ptr_in(1, int(8))),
+        "shared_i8ptr" => return Ok(ptr_in(3, int(8))),
+        "shared_i64ptr" => return Ok(ptr_in(3, int(64))),
         "metadata" => return Ok(LLVMType::Metadata),
         "vararg" => return Ok(LLVMType::Vararg),
         "descriptor" => return Ok(LLVMType::Descriptor),
@@ -64,7 +117,6 @@ fn parse_internals(s: &str) -> Result<LLVMType, ()> {
             parse_internals(&s[v_len_idx..]).map(|t| LLVMType::Vector(Some((n, Box::new(t)))))
         })
     } else {
-        println!("unrecognised {}", s);
         Err(())
     }
 }
@@ -90,16 +142,32 @@ impl LLVMType {
                 "LLVMMatchType" => MatchStyle::Direct,
                 "LLVMExtendedType" => MatchStyle::Extend,
                 "LLVMTruncatedType" => MatchStyle::Truncate,
-                "LLVMAnyPointerType" => {
-                    return match t.args[0] {
-                        ast::Val::Type(ref t) => LLVMType::from_ast(t).map(ptr),
+                "LLVMAnyPointerType" | "LLVMPointerType" | "LLVMQualPointerType" => {
+                    let elem = match t.args[0] {
+                        ast::Val::Type(ref t) => try_opt!(LLVMType::from_ast(t)),
+                        _ => return None
+                    };
+                    let space = match (&*t.name, t.args.get(1)) {
+                        ("LLVMAnyPointerType", _) => None,
+                        ("LLVMPointerType", _) => Some(0),
+                        (_, Some(&ast::Val::Int(n))) if n >= 0 => Some(n as u32),
+                        _ => return None
+                    };
+                    return Some(LLVMType::Ptr(Box::new(elem), space))
+                }
+                "LLVMScalarOrSameVectorWidth" => {
+                    return match (&t.args[0], t.args.get(1)) {
+                        (&ast::Val::Int(n), Some(&ast::Val::Type(ref elem))) if n >= 0 => {
+                            let elem = try_opt!(LLVMType::from_ast(elem));
+                            Some(LLVMType::SameWidth(n as u32, Box::new(elem)))
+                        }
                         _ => None
                     }
                 }
                 _ => return None
             };
             let n  = match t.args[0] {
-                ast::Val::Int(n) => n,
+                ast::Val::Int(n) if n >= 0 => n as u32,
                 _ => return None,
             };
             Some(LLVMType::MatchedType(n, style))
@@ -108,12 +176,12 @@ impl LLVMType {
 
     fn kind(&self) -> TypeKind {
         match *self {
-            LLVMType::MatchedType(n, _) => TypeKind::Matched(n),
+            LLVMType::MatchedType(n, _) | LLVMType::SameWidth(n, _) => TypeKind::Matched(n),
             LLVMType::Vector(None) | LLVMType::Int(None) |
-            LLVMType::Float(None) | LLVMType::Ptr(None)
+            LLVMType::Float(None) | LLVMType::Ptr(_, None)
                 => TypeKind::Generic,
             LLVMType::Vector(Some((_, ref ty))) |
-                LLVMType::Ptr(Some(ref ty)) | LLVMType::Mips(ref ty) => ty.kind(),
+                LLVMType::Ptr(ref ty, Some(_)) | LLVMType::Mips(ref ty) => ty.kind(),
 
             _ => TypeKind::Concrete
         }
@@ -124,17 +192,20 @@ impl LLVMType {
         match *self {
             LLVMType::Int(Some(n)) => format!("{}i{}", dot, n),
             LLVMType::Float(Some(n)) => format!("{}f{}", dot, n),
+            LLVMType::BFloat => format!("{}bf16", dot),
             LLVMType::Vector(Some((n, ref ty))) => format!("{}v{}{}", dot, n, ty.string(false)),
-            LLVMType::Ptr(Some(ref ty)) => format!("{}p0{}", dot, ty.string(false)),
+            LLVMType::Ptr(ref ty, Some(space)) => {
+                format!("{}p{}{}", dot, space, ty.string(false))
+            }
             _ => panic!("unsupported string {:?}", self),
         }
     }
 
-    fn choices(&self, generics: &[(usize, bool)],
+    fn choices(&self, generics: &[(usize, bool)], widths: &[u32],
                r: &[LLVMType], p: &[LLVMType]) -> (bool, Vec<LLVMType>) {
 
-        fn vectorify(tys: &[LLVMType], include_one: bool) -> Vec<LLVMType> {
-            let count = 1 + if include_one {1} else {0};
+        fn vectorify(tys: &[LLVMType], include_one: bool, widths: &[u32]) -> Vec<LLVMType> {
+            let count = widths.len() + if include_one {1} else {0};
             let mut ret = Vec::with_capacity(count * tys.len());
 
             for ty in tys.iter() {
@@ -142,65 +213,153 @@ impl LLVMType {
                     LLVMType::Int(Some(n)) | LLVMType::Float(Some(n)) => n,
                     _ => panic!("invalid vectorification {:?}", ty)
                 };
-                ret.push(LLVMType::Vector(Some((128 / width, Box::new(ty.clone())))));
+                for &total in widths.iter() {
+                    let lanes = total / width;
+                    if lanes * width == total && simdty_has_lanes(lanes) {
+                        ret.push(LLVMType::Vector(Some((lanes, Box::new(ty.clone())))));
+                    }
+                }
                 if include_one {
                     ret.push(ty.clone())
                 }
             }
-            return ret
-            /*let lens = [2, 4, 8];
-            let mut ret = Vec::with_capacity((lens.len() + 1) * tys.len());
-            ret.extend(tys.iter().cloned());
-
-            for &len in lens.iter() {
-                for ty in tys.iter() {
-                    ret.push(LLVMType::Vector(Some((len, Box::new(ty.clone())))))
-                }
-            }
-            ret*/
+            ret
         }
 
         match *self {
             LLVMType::MatchedType(n, style) => {
-                if style != MatchStyle::Direct { return (false, vec![]) }
+                let (idx, use_r) = generics[n as usize];
+                let matched = if use_r {&r[idx]} else {&p[idx]};
 
+                // like LLVM, extending and truncating change the width
+                // of the elements, and preserve the lane count. LLVM
+                // only truncates floats, and only in vectors (e.g.
+                // `<2 x double>` to `<2 x float>`).
+                let resize = |ty: &LLVMType, vector: bool| {
+                    let (w, is_float) = match (ty, style) {
+                        (&LLVMType::Int(Some(w)), _) => (w, false),
+                        (&LLVMType::Float(Some(w)), MatchStyle::Truncate) if vector => (w, true),
+                        _ => return None
+                    };
+                    let w = match style {
+                        MatchStyle::Direct => w,
+                        MatchStyle::Extend => w * 2,
+                        MatchStyle::Truncate => w / 2,
+                    };
+                    match (is_float, w) {
+                        (false, 8) | (false, 16) | (false, 32) | (false, 64) => Some(int(w)),
+                        (true, 16) | (true, 32) => Some(float(w)),
+                        _ => None
+                    }
+                };
+                let ty = match (style, matched) {
+                    (MatchStyle::Direct, ty) => Some(ty.clone()),
+                    // the resized vector has to be one of the widths too
+                    (_, &LLVMType::Vector(Some((lanes, ref elem)))) => {
+                        resize(elem, true).and_then(|elem| {
+                            let bits = match elem {
+                                LLVMType::Int(Some(w)) | LLVMType::Float(Some(w)) => w * lanes,
+                                _ => 0
+                            };
+                            if widths.contains(&bits) {
+                                Some(LLVMType::Vector(Some((lanes, Box::new(elem)))))
+                            } else {
+                                None
+                            }
+                        })
+                    }
+                    (_, ty) => resize(ty, false),
+                };
+                (false, ty.into_iter().collect())
+            }
+            LLVMType::SameWidth(n, ref elem) => {
                 let (idx, use_r) = generics[n as usize];
-                (false, vec![if use_r {&r[idx]} else {&p[idx]}.clone()])
+                let matched = if use_r {&r[idx]} else {&p[idx]};
+                let ty = match *matched {
+                    LLVMType::Vector(Some((lanes, _))) => LLVMType::Vector(Some((lanes, elem.clone()))),
+                    _ => (**elem).clone(),
+                };
+                (false, vec![ty])
+            }
+            LLVMType::Int(None) => {
+                (true, vectorify(&[int(8), int(16), int(32), int(64)], true, widths))
+            }
+            LLVMType::Float(None) => {
+                (true, vectorify(&[float(16), float(32), float(64)], true, widths))
             }
-            LLVMType::Int(None) => (true, vectorify(&[int(8), int(16), int(32), int(64)], true)),
-            LLVMType::Float(None) => (true, vectorify(&[float(32), float(64)], true)),
             LLVMType::Vector(None) => {
-                (true, vectorify(&[int(8), int(16), int(32), int(64), float(32), float(64)], false))
+                (true, vectorify(&[int(8), int(16), int(32), int(64),
+                                   float(16), float(32), float(64)],
+                                 false, widths))
             }
             LLVMType::Vector(Some((n, ref ty))) => {
-                let (generic, choices) = ty.choices(generics, r, p);
+                let (generic, choices) = ty.choices(generics, widths, r, p);
 
                 (generic,
                  choices.into_iter()
                  .map(|ty| LLVMType::Vector(Some((n, Box::new(ty)))))
                  .collect())
             }
-            LLVMType::Ptr(None) => {
-                (true, vec![ptr(int(8))])
-            }
-            LLVMType::Ptr(Some(ref ty)) => {
-                let (generic, choices) = ty.choices(generics, r, p);
-                (generic, choices.into_iter().map(ptr).collect())
+            LLVMType::Ptr(ref ty, space) => {
+                let (generic, elems) = ty.choices(generics, widths, r, p);
+                // pointers overloaded over their address space are only
+                // bound in address space 0
+                (generic || space.is_none(),
+                 elems.into_iter().map(|elem| ptr_in(space.unwrap_or(0), elem)).collect())
             }
             _ => (false, vec![self.clone()]),
         }
     }
 }
 
+impl fmt::Display for LLVMType {
+    /// Formats the type in LLVM IR syntax, e.g. `<4 x float>`.
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LLVMType::Int(Some(n)) => write!(fmt, "i{}", n),
+            LLVMType::Int(None) => fmt.write_str("anyint"),
+            LLVMType::Float(Some(16)) => fmt.write_str("half"),
+            LLVMType::Float(Some(32)) => fmt.write_str("float"),
+            LLVMType::Float(Some(64)) => fmt.write_str("double"),
+            LLVMType::Float(Some(80)) => fmt.write_str("x86_fp80"),
+            LLVMType::Float(Some(128)) => fmt.write_str("fp128"),
+            LLVMType::Float(Some(n)) => write!(fmt, "f{}", n),
+            LLVMType::Float(None) => fmt.write_str("anyfloat"),
+            LLVMType::BFloat => fmt.write_str("bfloat"),
+            LLVMType::FixedPoint(n) => write!(fmt, "q{}", n),
+            LLVMType::Ptr(ref ty, Some(0)) => write!(fmt, "{}*", ty),
+            LLVMType::Ptr(ref ty, Some(space)) => write!(fmt, "{} addrspace({})*", ty, space),
+            LLVMType::Ptr(ref ty, None) => write!(fmt, "anyptr<{}>", ty),
+            LLVMType::Vector(Some((n, ref ty))) => write!(fmt, "<{} x {}>", n, ty),
+            LLVMType::Vector(None) => fmt.write_str("anyvector"),
+            LLVMType::Metadata => fmt.write_str("metadata"),
+            LLVMType::Vararg => fmt.write_str("..."),
+            LLVMType::Descriptor => fmt.write_str("descriptor"),
+            LLVMType::X86mmx => fmt.write_str("x86_mmx"),
+            LLVMType::Mips(ref ty) => fmt::Display::fmt(ty, fmt),
+            LLVMType::MatchedType(n, MatchStyle::Direct) => write!(fmt, "match<{}>", n),
+            LLVMType::MatchedType(n, MatchStyle::Extend) => write!(fmt, "extend<{}>", n),
+            LLVMType::MatchedType(n, MatchStyle::Truncate) => write!(fmt, "truncate<{}>", n),
+            LLVMType::SameWidth(n, ref ty) => write!(fmt, "same_width<{}, {}>", n, ty),
+        }
+    }
+}
+
 impl LLVMType {
     pub fn to_concrete_rust_string(&self) -> Option<String> {
         match *self {
             LLVMType::Int(Some(1)) => Some("bool".to_string()),
             LLVMType::Int(Some(n)) => Some(format!("i{}", n)),
-            LLVMType::Float(Some(n)) => Some(format!("f{}", n)),
-            LLVMType::Ptr(Some(ref ty))
-                => ty.to_concrete_rust_string().map(|s| format!("*mut {}", s)),
+            // Rust only has `f32` and `f64`
+            LLVMType::Float(Some(n)) if n == 32 || n == 64 => Some(format!("f{}", n)),
+            // Rust pointers are all in address space 0
+            LLVMType::Ptr(ref ty, Some(0)) => {
+                ty.to_concrete_rust_string().map(|ty| format!("*mut {}", ty))
+            }
             LLVMType::Vararg => Some("...".to_string()),
+            // nightly-2022-06-01 passes a 64-bit SIMD struct as
+            // `<1 x i64>`, which LLVM doesn't accept as an `x86_mmx`
+            LLVMType::X86mmx => None,
             LLVMType::Mips(ref ty) => ty.to_concrete_rust_string(),
 
             LLVMType::Vector(Some((n, ref ty))) => {
@@ -209,7 +368,7 @@ impl LLVMType {
                     LLVMType::Float(Some(n)) if n == 32 || n == 64 => ("f", n),
                     _ => return None
                 };
-                if size.count_ones() == 1 && 2 <= size && size <= 64 {
+                if simdty_has_lanes(n) {
                     Some(format!("::simdty::{}{}x{}", name, size, n))
                 } else {
                     None
@@ -225,14 +384,23 @@ impl LLVMType {
 pub enum Arch {
     AMDGPU,
     Aarch64,
+    Amdgcn,
     Arm,
+    Bpf,
     Cuda,
+    Dx,
     Hexagon,
+    LoongArch,
     Mips,
     Nvvm,
     Ppc,
     Ptx,
     R600,
+    Riscv,
+    S390,
+    Spv,
+    Ve,
+    Wasm,
     X86,
     Xcore,
 }
@@ -241,33 +409,107 @@ impl Arch {
         match *self {
             Arch::AMDGPU => "AMDGPU",
             Arch::Aarch64 => "aarch64",
+            Arch::Amdgcn => "amdgcn",
             Arch::Arm => "arm",
+            Arch::Bpf => "bpf",
             Arch::Cuda => "cuda",
+            Arch::Dx => "dx",
             Arch::Hexagon => "hexagon",
+            Arch::LoongArch => "loongarch",
             Arch::Mips => "mips",
             Arch::Nvvm => "nvvm",
             Arch::Ppc => "ppc",
             Arch::Ptx => "ptx",
             Arch::R600 => "r600",
+            Arch::Riscv => "riscv",
+            Arch::S390 => "s390",
+            Arch::Spv => "spv",
+            Arch::Ve => "ve",
+            Arch::Wasm => "wasm",
             Arch::X86 => "x86",
             Arch::Xcore => "xcore",
         }
     }
 }
+impl Arch {
+    /// The values of `target_arch` that can run this architecture's
+    /// intrinsics. This is empty for targets that Rust doesn't
+    /// support, whose modules are only enabled by `feature()`.
+    pub fn target_arches(&self) -> &'static [&'static str] {
+        // constants, since a `&[...]` in an arm would need promoting to
+        // a static
+        const AMDGPU: &'static [&'static str] = &["amdgpu"];
+        const AARCH64: &'static [&'static str] = &["aarch64"];
+        const ARM: &'static [&'static str] = &["arm"];
+        const BPF: &'static [&'static str] = &["bpf"];
+        const NVPTX: &'static [&'static str] = &["nvptx", "nvptx64"];
+        const HEXAGON: &'static [&'static str] = &["hexagon"];
+        const LOONGARCH: &'static [&'static str] = &["loongarch64"];
+        const MIPS: &'static [&'static str] = &["mips", "mips64"];
+        const POWERPC: &'static [&'static str] = &["powerpc", "powerpc64"];
+        const RISCV: &'static [&'static str] = &["riscv32", "riscv64"];
+        const S390: &'static [&'static str] = &["s390x"];
+        const SPIRV: &'static [&'static str] = &["spirv"];
+        const WASM: &'static [&'static str] = &["wasm32", "wasm64"];
+        const X86: &'static [&'static str] = &["x86", "x86_64"];
+        const XCORE: &'static [&'static str] = &["xcore"];
+        const NONE: &'static [&'static str] = &[];
+        match *self {
+            Arch::AMDGPU | Arch::Amdgcn | Arch::R600 => AMDGPU,
+            Arch::Aarch64 => AARCH64,
+            Arch::Arm => ARM,
+            Arch::Bpf => BPF,
+            Arch::Cuda | Arch::Nvvm | Arch::Ptx => NVPTX,
+            Arch::Hexagon => HEXAGON,
+            Arch::LoongArch => LOONGARCH,
+            Arch::Mips => MIPS,
+            Arch::Ppc => POWERPC,
+            Arch::Riscv => RISCV,
+            Arch::S390 => S390,
+            Arch::Spv => SPIRV,
+            Arch::Wasm => WASM,
+            Arch::X86 => X86,
+            Arch::Xcore => XCORE,
+            Arch::Dx | Arch::Ve => NONE,
+        }
+    }
+
+    /// The cargo feature that enables this architecture's module on
+    /// every target.
+    pub fn feature(&self) -> String {
+        format!("arch-{}", self.as_str().to_lowercase())
+    }
+
+    /// The `cfg` attribute gating this architecture's module.
+    pub fn cfg_attr(&self) -> String {
+        let mut conds = vec![format!("feature = \"{}\"", self.feature())];
+        conds.extend(self.target_arches().iter().map(|a| format!("target_arch = \"{}\"", a)));
+        format!("#[cfg(any({}))]", conds.connect(", "))
+    }
+}
 impl FromStr for Arch {
     type Err = ();
     fn from_str(s: &str) -> Result<Arch, ()> {
         Ok(match s {
             "AMDGPU" => Arch::AMDGPU,
             "aarch64" => Arch::Aarch64,
+            "amdgcn" => Arch::Amdgcn,
             "arm" => Arch::Arm,
+            "bpf" => Arch::Bpf,
             "cuda" => Arch::Cuda,
+            "dx" => Arch::Dx,
             "hexagon" => Arch::Hexagon,
+            "loongarch" => Arch::LoongArch,
             "mips" => Arch::Mips,
             "nvvm" => Arch::Nvvm,
             "ppc" => Arch::Ppc,
             "ptx" => Arch::Ptx,
             "r600" => Arch::R600,
+            "riscv" => Arch::Riscv,
+            "s390" => Arch::S390,
+            "spv" => Arch::Spv,
+            "ve" => Arch::Ve,
+            "wasm" => Arch::Wasm,
             "x86" => Arch::X86,
             "xcore" => Arch::Xcore,
             _ => return Err(())
@@ -278,26 +520,410 @@ impl fmt::Display for Arch {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(self.as_str(), fmt) }
 }
 
+/// The properties in the third argument of `Intrinsic<...>`.
+///
+/// Those that refer to an argument store its (zero-based) parameter
+/// index.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum IntrinsicProperty {
+    NoMem,
+    ReadMem,
+    WriteMem,
+    ReadArgMem,
+    ReadWriteArgMem,
+    ArgMemOnly,
+    Commutative,
+    Throws,
+    NoReturn,
+    NoDuplicate,
+    Convergent,
+    WillReturn,
+    NoSync,
+    Speculatable,
+    HasSideEffects,
+
+    NoCapture(u32),
+    NoAlias(u32),
+    ReadOnly(u32),
+    WriteOnly(u32),
+    ReadNone(u32),
+    Returned(u32),
+    ImmArg(u32),
+
+    Other(String),
+}
+
+impl IntrinsicProperty {
+    fn from_ast(t: &ast::Type) -> Option<IntrinsicProperty> {
+        use self::IntrinsicProperty::*;
+
+        if t.args.is_empty() {
+            return Some(match &*t.name {
+                "IntrNoMem" => NoMem,
+                "IntrReadMem" => ReadMem,
+                "IntrWriteMem" => WriteMem,
+                "IntrReadArgMem" => ReadArgMem,
+                "IntrReadWriteArgMem" => ReadWriteArgMem,
+                "IntrArgMemOnly" => ArgMemOnly,
+                "Commutative" => Commutative,
+                "Throws" => Throws,
+                "IntrNoReturn" => NoReturn,
+                "IntrNoDuplicate" => NoDuplicate,
+                "IntrConvergent" => Convergent,
+                "IntrWillReturn" => WillReturn,
+                "IntrNoSync" => NoSync,
+                "IntrSpeculatable" => Speculatable,
+                "IntrHasSideEffects" => HasSideEffects,
+                name => Other(name.to_string()),
+            })
+        }
+
+        // newer LLVMs wrap the index as `ArgIndex<n>`
+        let n = match t.args[0] {
+            ast::Val::Int(n) if n >= 0 => n as u32,
+            ast::Val::Type(ref idx) if idx.name == "ArgIndex" && idx.args.len() == 1 => {
+                match idx.args[0] {
+                    ast::Val::Int(n) if n >= 0 => n as u32,
+                    _ => return None
+                }
+            }
+            _ => return Some(Other(t.name.clone()))
+        };
+        Some(match &*t.name {
+            "NoCapture" => NoCapture(n),
+            "NoAlias" => NoAlias(n),
+            "ReadOnly" => ReadOnly(n),
+            "WriteOnly" => WriteOnly(n),
+            "ReadNone" => ReadNone(n),
+            "Returned" => Returned(n),
+            "ImmArg" => ImmArg(n),
+            name => Other(name.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for IntrinsicProperty {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::IntrinsicProperty::*;
+        let (name, arg) = match *self {
+            NoMem => ("IntrNoMem", None),
+            ReadMem => ("IntrReadMem", None),
+            WriteMem => ("IntrWriteMem", None),
+            ReadArgMem => ("IntrReadArgMem", None),
+            ReadWriteArgMem => ("IntrReadWriteArgMem", None),
+            ArgMemOnly => ("IntrArgMemOnly", None),
+            Commutative => ("Commutative", None),
+            Throws => ("Throws", None),
+            NoReturn => ("IntrNoReturn", None),
+            NoDuplicate => ("IntrNoDuplicate", None),
+            Convergent => ("IntrConvergent", None),
+            WillReturn => ("IntrWillReturn", None),
+            NoSync => ("IntrNoSync", None),
+            Speculatable => ("IntrSpeculatable", None),
+            HasSideEffects => ("IntrHasSideEffects", None),
+            NoCapture(n) => ("NoCapture", Some(n)),
+            NoAlias(n) => ("NoAlias", Some(n)),
+            ReadOnly(n) => ("ReadOnly", Some(n)),
+            WriteOnly(n) => ("WriteOnly", Some(n)),
+            ReadNone(n) => ("ReadNone", Some(n)),
+            Returned(n) => ("Returned", Some(n)),
+            ImmArg(n) => ("ImmArg", Some(n)),
+            Other(ref s) => (&**s, None),
+        };
+        match arg {
+            Some(n) => write!(fmt, "{}<{}>", name, n),
+            None => fmt.write_str(name),
+        }
+    }
+}
+
+/// An x86 instruction set extension, whose intrinsics are grouped
+/// into a submodule of `x86`.
+pub struct X86Extension {
+    /// The name of the submodule.
+    pub module: &'static str,
+    /// The `target_feature` required to use the intrinsics.
+    pub feature: &'static str,
+    /// The prefixes (of the name with `int_x86_` stripped) of the
+    /// intrinsics in this extension.
+    prefixes: &'static [&'static str],
+}
+
+macro_rules! x86_extensions {
+    ($($module: ident => $feature: expr, [$($prefix: expr),*];)*) => {
+        &[$(X86Extension {
+            module: stringify!($module),
+            feature: $feature,
+            prefixes: &[$($prefix),*],
+        }),*]
+    }
+}
+
+/// The x86 extensions with submodules, in the order they are emitted.
+pub static X86_EXTENSIONS: &'static [X86Extension] = x86_extensions! {
+    mmx => "mmx", ["mmx_"];
+    sse => "sse", ["sse_"];
+    sse2 => "sse2", ["sse2_"];
+    sse3 => "sse3", ["sse3_"];
+    ssse3 => "ssse3", ["ssse3_"];
+    sse41 => "sse4.1", ["sse41_"];
+    sse42 => "sse4.2", ["sse42_"];
+    sse4a => "sse4a", ["sse4a_"];
+    avx => "avx", ["avx_"];
+    avx2 => "avx2", ["avx2_"];
+    avx512f => "avx512f", ["avx512_"];
+    avx512bw => "avx512bw", ["avx512_dbpsadbw_", "avx512_kadd_d", "avx512_kadd_q",
+                             "avx512_ktestc_d", "avx512_ktestc_q",
+                             "avx512_ktestz_d", "avx512_ktestz_q",
+                             "avx512_mask_pmov_wb_", "avx512_mask_pmovs_wb_",
+                             "avx512_mask_pmovus_wb_",
+                             "avx512_packssdw_", "avx512_packsswb_",
+                             "avx512_packusdw_", "avx512_packuswb_", "avx512_pavg_",
+                             "avx512_permvar_hi_", "avx512_pmaddubs_w_", "avx512_pmaddw_d_",
+                             "avx512_pmul_hr_sw_", "avx512_pmulh_w_", "avx512_pmulhu_w_",
+                             "avx512_psad_bw_", "avx512_pshuf_b_",
+                             "avx512_psll_w_", "avx512_pslli_w_", "avx512_psllv_w_",
+                             "avx512_psra_w_", "avx512_psrai_w_", "avx512_psrav_w_",
+                             "avx512_psrl_w_", "avx512_psrli_w_", "avx512_psrlv_w_",
+                             "avx512_vpermi2var_hi_"];
+    avx512dq => "avx512dq", ["avx512_fpclass_", "avx512_kadd_b", "avx512_kadd_w",
+                             "avx512_ktestc_b", "avx512_ktestc_w",
+                             "avx512_ktestz_b", "avx512_ktestz_w",
+                             "avx512_mask_cvtpd2qq_", "avx512_mask_cvtpd2uqq_",
+                             "avx512_mask_cvtps2qq_", "avx512_mask_cvtps2uqq_",
+                             "avx512_mask_cvtqq2ps_", "avx512_mask_cvtuqq2ps_",
+                             "avx512_mask_cvttpd2qq_", "avx512_mask_cvttpd2uqq_",
+                             "avx512_mask_cvttps2qq_", "avx512_mask_cvttps2uqq_",
+                             "avx512_mask_fpclass_", "avx512_mask_range_",
+                             "avx512_mask_reduce_"];
+    avx512cd => "avx512cd", ["avx512_broadcastmb_", "avx512_broadcastmw_",
+                             "avx512_conflict_", "avx512_mask_conflict_",
+                             "avx512_mask_lzcnt_"];
+    avx512er => "avx512er", ["avx512_rcp28_", "avx512_rsqrt28_", "avx512_exp2_"];
+    avx512pf => "avx512pf", ["avx512_gatherpf_", "avx512_scatterpf_"];
+    avx512vbmi => "avx512vbmi", ["avx512_permvar_qi_", "avx512_pmultishift_qb_",
+                                 "avx512_vpermi2var_qi_"];
+    avx512ifma => "avx512ifma", ["avx512_vpmadd52"];
+    avx512vnni => "avx512vnni", ["avx512_vpdpbusd", "avx512_vpdpwssd"];
+    avx512bitalg => "avx512bitalg", ["avx512_vpshufbitqmb_"];
+    avx512vp2intersect => "avx512vp2intersect", ["avx512_vp2intersect_"];
+    avx512bf16 => "avx512bf16", ["avx512bf16_"];
+    avx512fp16 => "avx512fp16", ["avx512fp16_"];
+    fma => "fma", ["fma_"];
+    xop => "xop", ["xop_"];
+    f16c => "f16c", ["vcvtph2ps_", "vcvtps2ph_"];
+    aes => "aes", ["aesni_"];
+    pclmul => "pclmulqdq", ["pclmulqdq"];
+    sha => "sha", ["sha1", "sha256"];
+    bmi => "bmi", ["bmi_bextr_"];
+    bmi2 => "bmi2", ["bmi_bzhi_", "bmi_pdep_", "bmi_pext_"];
+    tbm => "tbm", ["tbm_"];
+    adx => "adx", ["addcarryx_"];
+    rdrand => "rdrand", ["rdrand_"];
+    rdseed => "rdseed", ["rdseed_"];
+    fsgsbase => "fsgsbase", ["rdfsbase_", "rdgsbase_", "wrfsbase_", "wrgsbase_"];
+    rtm => "rtm", ["xbegin", "xend", "xabort", "xtest"];
+};
+
+/// The extension that the x86 intrinsic `name` (with `int_x86_`
+/// stripped) belongs to, if any. The longest matching prefix wins,
+/// so e.g. `avx512_rcp28_ps` is in `avx512er`, not `avx512f`.
+pub fn x86_extension(name: &str) -> Option<&'static X86Extension> {
+    let mut best = None;
+    let mut best_len = 0;
+    for ext in X86_EXTENSIONS.iter() {
+        for prefix in ext.prefixes.iter() {
+            if name.starts_with(prefix) && prefix.len() > best_len {
+                best = Some(ext);
+                best_len = prefix.len();
+            }
+        }
+    }
+    best
+}
+
+/// A restriction on the types an overloaded type can be instantiated
+/// at, beyond what its TableGen type says.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Overload {
+    /// Scalars only, no vectors.
+    Scalar,
+    /// `i32` or `i64` only, e.g. lengths and object sizes.
+    PointerSized,
+    /// Elements with a whole number of 16-bit halves, e.g. for byte
+    /// swapping.
+    EvenBytes,
+}
+
+impl Overload {
+    fn allows(&self, ty: &LLVMType) -> bool {
+        // the restrictions apply to the type pointed to
+        let ty = match *ty {
+            LLVMType::Ptr(ref ty, _) => &**ty,
+            ref ty => ty
+        };
+        match *self {
+            Overload::Scalar => match *ty {
+                LLVMType::Vector(_) => false,
+                _ => true
+            },
+            Overload::PointerSized => *ty == int(32) || *ty == int(64),
+            Overload::EvenBytes => match *ty {
+                LLVMType::Int(Some(n)) => n % 16 == 0,
+                LLVMType::Vector(Some((_, ref elem))) => match **elem {
+                    LLVMType::Int(Some(n)) => n % 16 == 0,
+                    _ => true
+                },
+                _ => true
+            },
+        }
+    }
+}
+
+/// The restrictions on overloaded intrinsics that LLVM's verifier
+/// imposes: the intrinsic, the index of the overloaded type (counting
+/// return values then parameters, as `LLVMMatchType` does) and the
+/// restriction.
+static OVERLOADS: &'static [(&'static str, usize, Overload)] = &[
+    ("int_memcpy", 2, Overload::PointerSized),
+    ("int_memcpy_inline", 2, Overload::PointerSized),
+    ("int_memmove", 2, Overload::PointerSized),
+    ("int_memset", 1, Overload::PointerSized),
+    ("int_memcpy_element_unordered_atomic", 2, Overload::PointerSized),
+    ("int_memmove_element_unordered_atomic", 2, Overload::PointerSized),
+    ("int_memset_element_unordered_atomic", 1, Overload::PointerSized),
+    ("int_objectsize", 0, Overload::PointerSized),
+    ("int_wasm_memory_size", 0, Overload::PointerSized),
+    ("int_wasm_memory_grow", 0, Overload::PointerSized),
+    ("int_wasm_tls_size", 0, Overload::PointerSized),
+    ("int_wasm_tls_align", 0, Overload::PointerSized),
+    ("int_bswap", 0, Overload::EvenBytes),
+    ("int_annotation", 0, Overload::Scalar),
+    ("int_ptr_annotation", 0, Overload::Scalar),
+    ("int_convert_to_fp16", 0, Overload::Scalar),
+    ("int_convert_from_fp16", 0, Overload::Scalar),
+    ("int_convertff", 0, Overload::Scalar), ("int_convertff", 1, Overload::Scalar),
+    ("int_convertfsi", 0, Overload::Scalar), ("int_convertfsi", 1, Overload::Scalar),
+    ("int_convertfui", 0, Overload::Scalar), ("int_convertfui", 1, Overload::Scalar),
+    ("int_convertsif", 0, Overload::Scalar), ("int_convertsif", 1, Overload::Scalar),
+    ("int_convertuif", 0, Overload::Scalar), ("int_convertuif", 1, Overload::Scalar),
+    ("int_convertss", 0, Overload::Scalar), ("int_convertss", 1, Overload::Scalar),
+    ("int_convertsu", 0, Overload::Scalar), ("int_convertsu", 1, Overload::Scalar),
+    ("int_convertus", 0, Overload::Scalar), ("int_convertus", 1, Overload::Scalar),
+    ("int_convertuu", 0, Overload::Scalar), ("int_convertuu", 1, Overload::Scalar),
+];
+
+/// A single instantiation of an (possibly overloaded) intrinsic.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Signature {
+    /// The suffix of the LLVM name selecting this instantiation,
+    /// e.g. `.v4f32`; empty if the intrinsic isn't overloaded.
+    pub suffix: String,
+    pub params: Vec<LLVMType>,
+    pub ret: Vec<LLVMType>,
+    /// The Rust parameters, e.g. `a: f32`, and `...` if variadic.
+    pub rust_params: Vec<String>,
+    /// The Rust return type, e.g. `f32`, or `()`.
+    pub rust_ret: String,
+}
+
+impl Signature {
+    /// The Rust signature, e.g. `(a: f32) -> f32`.
+    pub fn rust(&self) -> String {
+        format!("({}) -> {}", self.rust_params.connect(", "), self.rust_ret)
+    }
+}
+
+/// The target prefix of the intrinsic `d`, from its `TargetPrefix`
+/// field (usually set by an enclosing `let TargetPrefix = "..." in`),
+/// or `None` for a target-independent one.
+///
+/// Older .td files don't declare a prefix for every target, so a
+/// def without one is assumed to be for the architecture named by
+/// the word after `int_`, if there is one.
+pub fn target_prefix(d: &ast::Def) -> Option<String> {
+    match d.field("TargetPrefix") {
+        Some(&ast::Val::String(ref s)) if !s.is_empty() => Some(s.clone()),
+        _ => {
+            let arch = regex!(r"^int_([^_]*)");
+            arch.captures(&d.name)
+                .and_then(|c| c.at(1))
+                .and_then(|a| a.parse::<Arch>().ok())
+                .map(|a| a.as_str().to_string())
+        }
+    }
+}
+
+/// The first return or parameter type of the intrinsic `d` that isn't
+/// understood (e.g. `llvm_token_ty` or `LLVMType<v256i1>`), to explain
+/// why `Intrinsic::from_ast` failed.
+pub fn unrecognised_type(d: &ast::Def) -> Option<String> {
+    d.inherits.iter()
+        .filter(|sup| sup.name == "Intrinsic")
+        .flat_map(|sup| sup.args.iter().take(2))
+        .filter_map(|types| match *types {
+            ast::Val::List(ref types) => Some(types),
+            _ => None
+        })
+        .flat_map(|types| types.iter())
+        .filter_map(|t| match *t {
+            ast::Val::Type(ref t) if LLVMType::from_ast(t).is_none() => {
+                if t.args.is_empty() { return Some(t.name.clone()) }
+                let args = t.args.iter()
+                    .map(|a| match *a {
+                        ast::Val::Type(ref a) => a.name.clone(),
+                        ast::Val::Int(n) => n.to_string(),
+                        _ => "..".to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                Some(format!("{}<{}>", t.name, args.connect(", ")))
+            }
+            _ => None
+        })
+        .next()
+}
+
+/// An intrinsic, or an instantiation of one, for which no bindings
+/// were generated.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Skipped {
+    pub name: String,
+    /// The suffix of the instantiation, e.g. `.v4f16`; empty if the
+    /// whole intrinsic was skipped.
+    pub suffix: String,
+    pub arch: Option<Arch>,
+    pub reason: String,
+}
+
+/// The name of the `i`th parameter in the generated signatures.
+pub fn param_name(i: usize) -> char {
+    (b'a' + i as u8) as char
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Intrinsic {
     pub arch: Option<Arch>,
+    /// The `TargetPrefix`, even if there is no `Arch` for it.
+    pub target_prefix: Option<String>,
     pub name: String,
     pub gcc_name: Option<String>,
     pub llvm_name: Option<String>,
     pub params: Vec<LLVMType>,
     pub ret: Vec<LLVMType>,
+    pub properties: Vec<IntrinsicProperty>,
 }
 
 impl Intrinsic {
     pub fn from_ast(d: &ast::Def) -> Option<Intrinsic> {
         if !d.name.starts_with("int_") { return None }
-        let arch = regex!(r"^int_([^_]*)");
-        let arch = arch.captures(&d.name).unwrap().at(1).unwrap().parse();
+        let target_prefix = target_prefix(d);
+        let arch = target_prefix.as_ref().and_then(|p| p.parse().ok());
 
         let mut gcc_name = None;
         let mut llvm_name = None;
         let mut ret = vec![];
         let mut params = vec![];
+        let mut properties = vec![];
         for sup in d.inherits.iter() {
             match &*sup.name {
                 "GCCBuiltin" => {
@@ -333,6 +959,17 @@ impl Intrinsic {
                         }
                         _ => return None
                     }
+                    match sup.args[2] {
+                        ast::Val::List(ref props) => {
+                            properties = try_opt!(props.iter()
+                                .map(|v| match *v {
+                                    ast::Val::Type(ref t) => IntrinsicProperty::from_ast(t),
+                                    _ => None
+                                })
+                                .collect::<Option<_>>())
+                        }
+                        _ => return None
+                    }
                     match sup.args[3] {
                         ast::Val::String(ref s) => {
                             if !s.is_empty() { llvm_name = Some(s.clone()) }
@@ -345,126 +982,716 @@ impl Intrinsic {
         }
 
         Some(Intrinsic {
-            arch: arch.ok(),
+            arch: arch,
+            target_prefix: target_prefix,
             name: d.name.clone(),
             gcc_name: gcc_name,
             llvm_name: llvm_name,
             ret: ret,
             params: params,
+            properties: properties,
+        })
+    }
+
+    /// The name without `int_` and the target prefix, e.g.
+    /// `sse_max_ps` for `int_x86_sse_max_ps`.
+    pub fn short_name(&self) -> &str {
+        let name = &self.name["int_".len()..];
+        match self.target_prefix {
+            Some(ref p) if name.starts_with(&**p) && name[p.len()..].starts_with("_") => {
+                &name[p.len() + 1..]
+            }
+            _ => name
+        }
+    }
+
+    /// The (zero-based) indices of the parameters that must be
+    /// compile-time constants.
+    pub fn immediate_params(&self) -> Vec<usize> {
+        self.params_with(IntrinsicProperty::ImmArg)
+    }
+
+    /// The (zero-based) indices of the parameters that have the
+    /// property `prop`, e.g. `NoCapture` for those that are not
+    /// captured.
+    pub fn params_with(&self, prop: fn(u32) -> IntrinsicProperty) -> Vec<usize> {
+        (0..self.params.len())
+            .filter(|&i| self.properties.contains(&prop(i as u32)))
+            .collect()
+    }
+
+    /// Whether memory is only read through parameter `i` (or not
+    /// accessed through it at all), so that a pointer there can be
+    /// `*const`.
+    pub fn param_is_read_only(&self, i: usize) -> bool {
+        use self::IntrinsicProperty::*;
+        let has = |p: IntrinsicProperty| self.properties.contains(&p);
+        let n = i as u32;
+
+        if has(WriteOnly(n)) { return false }
+        has(ReadOnly(n)) || has(ReadNone(n)) ||
+            has(NoMem) || has(ReadMem) || has(ReadArgMem)
+    }
+
+    /// A short description of the memory behaviour implied by the
+    /// properties, if they say anything about it.
+    pub fn memory_summary(&self) -> Option<&'static str> {
+        use self::IntrinsicProperty::*;
+        let has = |p: IntrinsicProperty| self.properties.contains(&p);
+
+        let side_effects = has(HasSideEffects) || has(Throws) || has(NoReturn);
+        Some(if has(NoMem) {
+            if side_effects {
+                "Does not access memory."
+            } else {
+                "Pure: does not access memory and has no side effects."
+            }
+        } else if has(ReadArgMem) || (has(ReadMem) && has(ArgMemOnly)) {
+            "Only reads memory through its pointer arguments."
+        } else if has(ReadMem) {
+            "Only reads memory."
+        } else if has(WriteMem) && has(ArgMemOnly) {
+            "Only writes memory through its pointer arguments."
+        } else if has(WriteMem) {
+            "Only writes memory."
+        } else if has(ReadWriteArgMem) || has(ArgMemOnly) {
+            "Only accesses memory through its pointer arguments."
+        } else {
+            return None
         })
     }
 
 
-    pub fn signatures(&self) -> Vec<(String, String)> {
+    /// Each instantiation of this intrinsic that can be expressed in
+    /// Rust, with overloaded vector types instantiated at each of the
+    /// total bit-`widths` that the architecture has (all with the same
+    /// number of elements) and overloaded pointers in each address
+    /// space of the architecture, or the reason there are none.
+    pub fn signatures(&self, widths: &[u32]) -> Result<Vec<Signature>, String> {
+        self.signatures_and_dropped(widths, &mut vec![])
+    }
+
+    /// Like `signatures`, but also adds the suffix of each
+    /// instantiation that can't be expressed in Rust, and why, to
+    /// `dropped`.
+    pub fn signatures_and_dropped(&self, widths: &[u32], dropped: &mut Vec<(String, String)>)
+                                  -> Result<Vec<Signature>, String> {
         use std::iter::repeat;
         let mut generics = vec![];
 
+        // matched types can refer to overloaded types after them (like
+        // the results of `llvm.aarch64.neon.ld2` to the pointer), so the
+        // overloaded types are chosen first
+        let mut others = vec![];
         let ret_iter = self.ret.iter().zip(repeat(true).enumerate());
         let param_iter = self.params.iter().zip(repeat(false).enumerate());
         for (ty, pos) in ret_iter.chain(param_iter) {
             match ty.kind() {
                 TypeKind::Generic => generics.push(pos),
-                TypeKind::Matched(n) => assert!(generics.len() >= n as usize),
-                TypeKind::Concrete => {}
+                TypeKind::Matched(n) => others.push((pos, Some(n))),
+                TypeKind::Concrete => others.push((pos, None)),
+            }
+        }
+        for &(pos, n) in others.iter() {
+            if let Some(n) = n {
+                if n as usize >= generics.len() {
+                    let ty = if pos.1 {&self.ret[pos.0]} else {&self.params[pos.0]};
+                    return Err(format!("`{}` matches a missing overloaded type", ty))
+                }
             }
         }
+        let order = generics.iter()
+            .cloned()
+            .chain(others.into_iter().map(|(pos, _)| pos))
+            .collect::<Vec<_>>();
         let mut used_ret = self.ret.clone();
         let mut used_params = self.params.clone();
 
+        let overloads = OVERLOADS.iter()
+            .filter(|&&(name, _, _)| name == self.name)
+            .map(|&(_, idx, overload)| (idx, overload))
+            .collect::<Vec<_>>();
+
+        let arch_widths = vector_widths(self.arch);
+        let widths = widths.iter()
+            .cloned()
+            .filter(|w| arch_widths.contains(w))
+            .collect::<Vec<_>>();
+
+        let read_only = (0..self.params.len())
+            .map(|i| self.param_is_read_only(i))
+            .collect::<Vec<_>>();
+
         let mut sigs = vec![];
-        choose_types(&mut sigs, &generics,
-                     0, &self.ret,
-                     0, &self.params,
+        let mut skip = None;
+        choose_types(&mut sigs, &mut skip, dropped, &generics, &overloads,
+                     &widths, &read_only,
+                     &order, &self.ret, &self.params,
                      &mut vec![],
                      &mut used_ret, &mut used_params);
 
-        return sigs;
+        let immediates = self.immediate_params();
+        let (sigs, unwrapped): (Vec<_>, Vec<_>) = sigs.into_iter()
+            .map(|sig| {
+                let problem = undeclarable(&sig, &immediates);
+                (sig, problem)
+            })
+            .partition(|&(_, ref problem)| problem.is_none());
+        for (sig, problem) in unwrapped.into_iter() {
+            let problem = problem.unwrap();
+            if sigs.is_empty() {
+                return Err(problem)
+            }
+            dropped.push((sig.suffix, problem))
+        }
+        let sigs = sigs.into_iter().map(|(sig, _)| sig).collect::<Vec<_>>();
+
+        return if sigs.is_empty() {
+            Err(skip.unwrap_or_else(|| "no instantiation is accepted by LLVM".to_string()))
+        } else {
+            Ok(sigs)
+        };
 
 
-        fn choose_types(sigs: &mut Vec<(String, String)>,
-                        generics: &[(usize, bool)],
-                        ri: usize, ret: &[LLVMType],
-                        pi: usize, params: &[LLVMType],
+        fn allowed(overloads: &[(usize, Overload)], idx: usize, ty: &LLVMType) -> bool {
+            overloads.iter().all(|&(i, overload)| i != idx || overload.allows(ty))
+        }
+
+        // whether `ty` has as many elements as the overloaded vectors
+        // chosen before it: LLVM's intrinsics relate vectors
+        // element-wise, and without this every pair of vector
+        // parameters would multiply the number of instantiations
+        fn same_lanes(generics: &[(usize, bool)], chosen: usize,
+                      used_ret: &[LLVMType], used_params: &[LLVMType],
+                      ty: &LLVMType) -> bool {
+            fn lanes(ty: &LLVMType) -> Option<u32> {
+                match *ty {
+                    LLVMType::Vector(Some((n, _))) => Some(n),
+                    _ => None
+                }
+            }
+            let n = match lanes(ty) {
+                Some(n) => n,
+                None => return true
+            };
+            generics[..chosen].iter().all(|&(idx, is_ret)| {
+                let other = if is_ret {&used_ret[idx]} else {&used_params[idx]};
+                lanes(other).map_or(true, |m| m == n)
+            })
+        }
+
+        // why `sig` can't be declared in Rust: `...` needs a parameter
+        // before it, and immediates are the const generics of a
+        // wrapper, which can only be integers or `bool`, and which
+        // can't forward `...`
+        fn undeclarable(sig: &Signature, immediates: &[usize]) -> Option<String> {
+            const CONST_TYPES: &'static [&'static str] = &[
+                "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "bool"
+            ];
+            let variadic = sig.rust_params.last().map_or(false, |p| p == "...");
+            if variadic && sig.rust_params.len() == 1 {
+                return Some("`...` without a parameter before it".to_string())
+            }
+            if immediates.is_empty() { return None }
+            if variadic {
+                return Some("`...` with immediate parameters".to_string())
+            }
+            immediates.iter()
+                .map(|&i| &sig.params[i])
+                .find(|ty| {
+                    let rust = ty.to_concrete_rust_string().unwrap_or(String::new());
+                    !CONST_TYPES.contains(&&*rust)
+                })
+                .map(|ty| format!("immediate `{}`, which can't be a const generic", ty))
+        }
+
+        // records the first reason an instantiation was dropped
+        fn note(skip: &mut Option<String>, reason: String) {
+            if skip.is_none() { *skip = Some(reason) }
+        }
+
+        // a pointer that is only read through is `*const`
+        fn concrete(skip: &mut Option<String>, ty: &LLVMType, read_only: bool) -> Option<String> {
+            let s = match *ty {
+                LLVMType::Ptr(ref elem, Some(0)) if read_only => {
+                    elem.to_concrete_rust_string().map(|s| format!("*const {}", s))
+                }
+                _ => ty.to_concrete_rust_string()
+            };
+            if s.is_none() { note(skip, format!("unsupported type `{}`", ty)) }
+            s
+        }
+
+        // chooses the type at each of the positions in `order` in
+        // turn, and adds a signature for every combination
+        fn choose_types(sigs: &mut Vec<Signature>, skip: &mut Option<String>,
+                        dropped: &mut Vec<(String, String)>, generics: &[(usize, bool)], overloads: &[(usize, Overload)],
+                        widths: &[u32], read_only: &[bool],
+                        order: &[(usize, bool)], ret: &[LLVMType], params: &[LLVMType],
                         args: &mut Vec<String>,
                         used_ret: &mut [LLVMType], used_params: &mut [LLVMType]) {
-            match ret.get(ri) {
-                Some(rty) => {
-                    let (generic, choices) = rty.choices(generics,
-                                                         used_ret, used_params);
-                    for choice in choices.into_iter() {
-                        if generic {args.push(choice.string(true))};
-                        used_ret[ri] = choice;
-                        choose_types(sigs, generics,
-                                     ri + 1, ret,
-                                     pi, params,
-                                     args,
-                                     used_ret, used_params);
-                        if generic {args.pop();};
-                    }
-                    return
-                }
-                None => match params.get(pi) {
-                    Some(pty) => {
-                        let (generic, choices) = pty.choices(generics,
-                                                             used_ret, used_params);
-                        for choice in choices.into_iter() {
-                            if generic {args.push(choice.string(true))};
-                            used_params[pi] = choice;
-                            choose_types(sigs, generics,
-                                         ri, ret,
-                                         pi + 1, params,
-                                         args,
-                                         used_ret, used_params);
-                            if generic {args.pop();}
-                        }
-                        return
-                    }
-                    None => {}
+            if let Some(&(idx, is_ret)) = order.first() {
+                let ty = if is_ret {&ret[idx]} else {&params[idx]};
+                let (generic, choices) = ty.choices(generics, widths, used_ret, used_params);
+                if choices.is_empty() { note(skip, format!("no instantiation of `{}`", ty)) }
+                for choice in choices.into_iter() {
+                    if generic && !allowed(overloads, args.len(), &choice) { continue }
+                    if generic && !same_lanes(generics, args.len(), used_ret, used_params,
+                                             &choice) { continue }
+                    if generic {args.push(choice.string(true))};
+                    if is_ret {used_ret[idx] = choice} else {used_params[idx] = choice}
+                    choose_types(sigs, skip, dropped, generics, overloads,
+                                 widths, read_only,
+                                 &order[1..], ret, params,
+                                 args,
+                                 used_ret, used_params);
+                    if generic {args.pop();}
                 }
+                return
             }
 
+            // the first type that can't be expressed drops this
+            // instantiation
+            let mut reason = None;
             let params = used_params.iter()
                 .enumerate()
                 .map(|(i, ty)| {
-                    ty.to_concrete_rust_string()
+                    concrete(&mut reason, ty, read_only[i])
                         .map(|s| {
                             if s == "..." {
                                 s
                             } else {
-                                format!("{}: {}", (b'a' + i as u8) as char, s)
+                                format!("{}: {}", param_name(i), s)
                             }
                         })
                 })
                 .collect::<Option<Vec<_>>>();
-            let params = match params {
-                Some(p) => p.connect(", "),
-                None => return
-            };
 
+            // multiple return values are an LLVM literal struct, which
+            // is exposed as a tuple. rustc only passes a pair of scalars
+            // or one whose fields all have the same type as that struct:
+            // it may reorder and pad the fields of others, and `bool`
+            // fields are passed as `i8`
             let ret = match &*used_ret {
-                [] => "()".to_string(),
-                [ref ret] => match ret.to_concrete_rust_string() {
-                    Some(r) => r,
-                    None => return
-                },
-                _ => return
+                [] => Some("()".to_string()),
+                [ref ret] => concrete(&mut reason, ret, false),
+                rets => rets.iter()
+                    .map(|ty| concrete(&mut reason, ty, false))
+                    .collect::<Option<Vec<_>>>()
+                    .and_then(|r| {
+                        let tuple = format!("({})", r.connect(", "));
+                        let scalars = rets.iter().all(|ty| match *ty {
+                            LLVMType::Vector(_) => false,
+                            _ => true
+                        });
+                        if r.contains(&"bool".to_string()) ||
+                            !(scalars && r.len() == 2 || r.iter().all(|t| *t == r[0])) {
+                            note(&mut reason, format!("`{}` isn't passed as LLVM's struct",
+                                                      tuple));
+                            None
+                        } else {
+                            Some(tuple)
+                        }
+                    }),
+            };
+            let (params, ret) = match (params, ret, reason) {
+                (Some(p), Some(r), _) => (p, r),
+                (_, _, reason) => {
+                    let reason = reason.unwrap_or_else(|| "unsupported type".to_string());
+                    note(skip, reason.clone());
+                    dropped.push((args.concat(), reason));
+                    return
+                }
             };
 
-            let sig = format!("({}) -> {}", params, ret);
-            sigs.push((args.concat(), sig));
+            sigs.push(Signature {
+                suffix: args.concat(),
+                params: used_params.to_vec(),
+                ret: used_ret.to_vec(),
+                rust_params: params,
+                rust_ret: ret,
+            });
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{LLVMType, Intrinsic, Arch};
+    use super::{LLVMType, MatchStyle, Intrinsic, IntrinsicProperty, Arch};
+    use ast;
 
     #[test]
     fn llvm_type_parse() {
         assert_eq!("llvm_v8i16_ty".parse(),
-                   Some(LLVMType::Vector(Some((8, Box::new(LLVMType::Int(Some(16))))))));
+                   Ok(LLVMType::Vector(Some((8, Box::new(LLVMType::Int(Some(16))))))));
+    }
+
+    fn ty(name: &str, args: Vec<ast::Val>) -> ast::Type {
+        ast::Type { name: name.to_string(), args: args }
     }
 
+    fn intrinsic(name: &str, params: Vec<LLVMType>, ret: Vec<LLVMType>,
+                 props: Vec<IntrinsicProperty>) -> Intrinsic {
+        Intrinsic {
+            arch: None,
+            target_prefix: None,
+            name: name.to_string(),
+            gcc_name: None,
+            llvm_name: None,
+            params: params,
+            ret: ret,
+            properties: props,
+        }
+    }
+
+    fn rust(s: &str) -> Option<String> {
+        s.parse::<LLVMType>().ok().and_then(|t| t.to_concrete_rust_string())
+    }
+
+    #[test]
+    fn property_parse() {
+        assert_eq!(IntrinsicProperty::from_ast(&ty("IntrNoMem", vec![])),
+                   Some(IntrinsicProperty::NoMem));
+        assert_eq!(IntrinsicProperty::from_ast(&ty("ReadOnly", vec![ast::Val::Int(1)])),
+                   Some(IntrinsicProperty::ReadOnly(1)));
+        let idx = ast::Val::Type(ty("ArgIndex", vec![ast::Val::Int(2)]));
+        assert_eq!(IntrinsicProperty::from_ast(&ty("ImmArg", vec![idx])),
+                   Some(IntrinsicProperty::ImmArg(2)));
+        assert_eq!(IntrinsicProperty::from_ast(&ty("IntrFoo", vec![])),
+                   Some(IntrinsicProperty::Other("IntrFoo".to_string())));
+        assert_eq!(IntrinsicProperty::ImmArg(2).to_string(), "ImmArg<2>");
+    }
+
+    #[test]
+    fn immediate_params() {
+        let intr = intrinsic("int_cttz",
+                             vec![LLVMType::MatchedType(0, MatchStyle::Direct),
+                                  LLVMType::Int(Some(1))],
+                             vec![LLVMType::Int(None)],
+                             vec![IntrinsicProperty::NoMem, IntrinsicProperty::ImmArg(1)]);
+        assert_eq!(intr.immediate_params(), [1]);
+        assert_eq!(super::param_name(1), 'b');
+    }
+
+    #[test]
+    fn undeclarable() {
+        use super::IntrinsicProperty::ImmArg;
+        let mut intr = intrinsic("int_foo",
+                                 vec![LLVMType::Float(Some(32)), LLVMType::Int(Some(32))],
+                                 vec![],
+                                 vec![ImmArg(1)]);
+        assert!(intr.signatures(&[]).is_ok());
+
+        // immediates are const generics
+        intr.properties = vec![ImmArg(0)];
+        assert_eq!(intr.signatures(&[]),
+                   Err("immediate `float`, which can't be a const generic".to_string()));
+
+        // and a wrapper can't forward `...`
+        intr.params = vec![LLVMType::Int(Some(32)), LLVMType::Vararg];
+        assert_eq!(intr.signatures(&[]),
+                   Err("`...` with immediate parameters".to_string()));
+        intr.properties = vec![];
+        assert!(intr.signatures(&[]).is_ok());
+
+        intr.params = vec![LLVMType::Vararg];
+        assert_eq!(intr.signatures(&[]),
+                   Err("`...` without a parameter before it".to_string()));
+    }
+
+    #[test]
+    fn overload_constraints() {
+        fn suffixes(name: &str, params: Vec<LLVMType>,
+                    ret: Vec<LLVMType>) -> Result<Vec<String>, String> {
+            intrinsic(name, params, ret, vec![]).signatures(&[128])
+                .map(|sigs| sigs.into_iter().map(|s| s.suffix).collect())
+        }
+        let i32 = LLVMType::Int(Some(32));
+        let i1 = LLVMType::Int(Some(1));
+        let anyptr = LLVMType::Ptr(Box::new(LLVMType::Int(Some(8))), None);
+
+        // the overloads LLVM accepts
+        assert_eq!(suffixes("int_memcpy",
+                            vec![anyptr.clone(), anyptr.clone(), LLVMType::Int(None),
+                                 i32.clone(), i1.clone()],
+                            vec![]),
+                   Ok(vec![".p0i8.p0i8.i32".to_string(), ".p0i8.p0i8.i64".to_string()]));
+        assert_eq!(suffixes("int_memset",
+                            vec![anyptr.clone(), LLVMType::Int(Some(8)), LLVMType::Int(None),
+                                 i32.clone(), i1.clone()],
+                            vec![]),
+                   Ok(vec![".p0i8.i32".to_string(), ".p0i8.i64".to_string()]));
+        assert_eq!(suffixes("int_memcpy_inline",
+                            vec![anyptr.clone(), anyptr.clone(), LLVMType::Int(None), i1.clone()],
+                            vec![]),
+                   Ok(vec![".p0i8.p0i8.i32".to_string(), ".p0i8.p0i8.i64".to_string()]));
+        for name in ["int_memcpy_element_unordered_atomic",
+                     "int_memmove_element_unordered_atomic"].iter() {
+            assert_eq!(suffixes(name,
+                                vec![anyptr.clone(), anyptr.clone(), LLVMType::Int(None),
+                                     i32.clone()],
+                                vec![]),
+                       Ok(vec![".p0i8.p0i8.i32".to_string(), ".p0i8.p0i8.i64".to_string()]));
+        }
+        assert_eq!(suffixes("int_memset_element_unordered_atomic",
+                            vec![anyptr.clone(), LLVMType::Int(Some(8)), LLVMType::Int(None),
+                                 i32.clone()],
+                            vec![]),
+                   Ok(vec![".p0i8.i32".to_string(), ".p0i8.i64".to_string()]));
+        assert_eq!(suffixes("int_objectsize",
+                            vec![anyptr.clone(), i1.clone()],
+                            vec![LLVMType::Int(None)]),
+                   Ok(vec![".i32.p0i8".to_string(), ".i64.p0i8".to_string()]));
+        assert_eq!(suffixes("int_bswap",
+                            vec![LLVMType::MatchedType(0, MatchStyle::Direct)],
+                            vec![LLVMType::Int(None)]),
+                   Ok([".v8i16", ".i16", ".v4i32", ".i32", ".v2i64", ".i64"].iter()
+                      .map(|s| s.to_string()).collect()));
+        // unconstrained intrinsics are unaffected
+        assert_eq!(suffixes("int_ctpop",
+                            vec![LLVMType::MatchedType(0, MatchStyle::Direct)],
+                            vec![LLVMType::Int(None)]).map(|s| s.len()),
+                   Ok(8));
+        // and those with no instantiation LLVM accepts are skipped
+        assert_eq!(suffixes("int_convertff",
+                            vec![LLVMType::MatchedType(0, MatchStyle::Direct)],
+                            vec![LLVMType::Vector(None)]),
+                   Err("no instantiation is accepted by LLVM".to_string()));
+    }
+
+    #[test]
+    fn extend_truncate() {
+        fn rust_sigs(params: Vec<LLVMType>, ret: Vec<LLVMType>,
+                     widths: &[u32]) -> Result<Vec<String>, String> {
+            intrinsic("int_foo", params, ret, vec![]).signatures(widths)
+                .map(|sigs| sigs.into_iter().map(|s| s.rust()).collect())
+        }
+        let strings = |v: &[&str]| Ok(v.iter().map(|s| s.to_string()).collect());
+        // vmovn: narrowing, at the widths, so the 64-bit results take
+        // 128-bit vectors, and the 128-bit ones would take 256-bit ones
+        assert_eq!(rust_sigs(vec![LLVMType::MatchedType(0, MatchStyle::Extend)],
+                             vec![LLVMType::Vector(None)], &[64, 128]),
+                   strings(&["(a: ::simdty::i16x8) -> ::simdty::i8x8",
+                             "(a: ::simdty::i32x4) -> ::simdty::i16x4",
+                             "(a: ::simdty::i64x2) -> ::simdty::i32x2"]));
+        // vaddl: widening, with scalars too, and without the 64-bit
+        // results, which would take 32-bit vectors
+        assert_eq!(rust_sigs(vec![LLVMType::MatchedType(0, MatchStyle::Truncate)],
+                             vec![LLVMType::Int(None)], &[64, 128]),
+                   strings(&["(a: ::simdty::i8x8) -> ::simdty::i16x8",
+                             "(a: i8) -> i16",
+                             "(a: ::simdty::i16x4) -> ::simdty::i32x4",
+                             "(a: i16) -> i32",
+                             "(a: ::simdty::i32x2) -> ::simdty::i64x2",
+                             "(a: i32) -> i64"]));
+        // float vectors are truncated too (but `half` ones are left
+        // out), and LLVM doesn't extend floats, or resize scalar ones
+        assert_eq!(rust_sigs(vec![LLVMType::MatchedType(0, MatchStyle::Truncate)],
+                             vec![LLVMType::Float(None)], &[64, 128]),
+                   strings(&["(a: ::simdty::f32x2) -> ::simdty::f64x2"]));
+        assert_eq!(rust_sigs(vec![LLVMType::MatchedType(0, MatchStyle::Extend)],
+                             vec![LLVMType::Float(None)], &[128]),
+                   Err("no instantiation of `extend<0>`".to_string()));
+    }
+
+    #[test]
+    fn multiple_returns() {
+        // the overflow bit is a vector for vectors
+        let overflow = LLVMType::from_ast(&ty("LLVMScalarOrSameVectorWidth",
+                                              vec![ast::Val::Int(0),
+                                                   ast::Val::Type(ty("llvm_i1_ty", vec![]))]))
+            .unwrap();
+        let intr = intrinsic("int_sadd_with_overflow",
+                             vec![LLVMType::MatchedType(0, MatchStyle::Direct),
+                                  LLVMType::MatchedType(0, MatchStyle::Direct)],
+                             vec![LLVMType::Int(None), overflow],
+                             vec![IntrinsicProperty::NoMem]);
+        assert_eq!(intr.ret[1].to_string(), "same_width<0, i1>");
+
+        // the overflow bit is passed as an `i8` in a tuple (and the
+        // vector forms' is an `i1` vector)
+        assert_eq!(intr.signatures(&[]),
+                   Err("`(i8, bool)` isn't passed as LLVM's struct".to_string()));
+
+        let mut intr = intrinsic("int_x86_addcarry_32",
+                                 vec![LLVMType::Int(Some(8)), LLVMType::Int(Some(32)),
+                                      LLVMType::Int(Some(32))],
+                                 vec![LLVMType::Int(Some(8)), LLVMType::Int(Some(32))],
+                                 vec![IntrinsicProperty::NoMem]);
+        intr.arch = Some(Arch::X86);
+        intr.target_prefix = Some("x86".to_string());
+        let sig = intr.signatures(&[]).unwrap().pop().unwrap();
+        assert_eq!(sig.rust(), "(a: i8, b: i32, c: i32) -> (i8, i32)");
+        assert_eq!(sig.ret, [LLVMType::Int(Some(8)), LLVMType::Int(Some(32))]);
+
+        // rustc pads and reorders other tuples
+        let i32x4 = LLVMType::Vector(Some((4, Box::new(LLVMType::Int(Some(32))))));
+        intr.ret = vec![i32x4.clone(), i32x4.clone()];
+        assert_eq!(intr.signatures(&[]).unwrap()[0].rust(),
+                   "(a: i8, b: i32, c: i32) -> (::simdty::i32x4, ::simdty::i32x4)");
+        intr.ret = vec![LLVMType::Int(Some(8)), i32x4];
+        assert_eq!(intr.signatures(&[]),
+                   Err("`(i8, ::simdty::i32x4)` isn't passed as LLVM's struct".to_string()));
+    }
+
+    #[test]
+    fn vector_widths() {
+        let intr = intrinsic("int_sqrt",
+                             vec![LLVMType::MatchedType(0, MatchStyle::Direct)],
+                             vec![LLVMType::Float(None)],
+                             vec![]);
+        let suffixes = intr.signatures(super::DEFAULT_VECTOR_WIDTHS).unwrap().into_iter()
+            .map(|sig| sig.suffix)
+            .collect::<Vec<_>>();
+        assert_eq!(suffixes,
+                   [".v2f32", ".v4f32", ".v8f32", ".v16f32", ".f32",
+                    ".v2f64", ".v4f64", ".v8f64", ".f64"]);
+
+        // only the widths of the architecture's vectors
+        let suffixes = |arch, ret: LLVMType, param: LLVMType, widths: &[u32]| {
+            let mut intr = intrinsic("int_foo", vec![param], vec![ret], vec![]);
+            intr.arch = arch;
+            intr.signatures(widths).map(|sigs| {
+                sigs.into_iter().map(|sig| sig.suffix).collect::<Vec<_>>()
+            })
+        };
+        let matched = LLVMType::MatchedType(0, MatchStyle::Direct);
+        assert_eq!(suffixes(Some(Arch::Aarch64), LLVMType::Float(None), matched.clone(),
+                            super::DEFAULT_VECTOR_WIDTHS).unwrap(),
+                   [".v2f32", ".v4f32", ".f32", ".v2f64", ".f64"]);
+        // which for HVX are 512 and 1024 bits, e.g. `simdty::i32x32`
+        assert_eq!(suffixes(Some(Arch::Hexagon), LLVMType::Vector(None), matched.clone(),
+                            super::DEFAULT_VECTOR_WIDTHS).unwrap(),
+                   [".v64i8", ".v32i16", ".v64i16", ".v16i32", ".v32i32", ".v8i64", ".v16i64",
+                    ".v16f32", ".v32f32", ".v8f64", ".v16f64"]);
+        assert!(suffixes(Some(Arch::Riscv), LLVMType::Vector(None), matched.clone(),
+                         super::DEFAULT_VECTOR_WIDTHS).is_err());
+
+        // overloaded vectors have the same number of elements
+        let convert = suffixes(None, LLVMType::Int(None), LLVMType::Float(None), &[128]).unwrap();
+        let suffixes = convert.iter().filter(|s| s.starts_with(".v4i32")).collect::<Vec<_>>();
+        assert_eq!(suffixes, [".v4i32.v4f32", ".v4i32.f32", ".v4i32.f64"]);
+    }
+
+    #[test]
+    fn forward_matches() {
+        // `llvm.aarch64.neon.ld2`'s first result matches the second,
+        // which its pointer points to
+        let vector = LLVMType::MatchedType(0, MatchStyle::Direct);
+        let mut intr = intrinsic("int_aarch64_neon_ld2",
+                                 vec![LLVMType::Ptr(Box::new(vector.clone()), None)],
+                                 vec![vector, LLVMType::Vector(None)],
+                                 vec![IntrinsicProperty::ReadArgMem]);
+        intr.arch = Some(Arch::Aarch64);
+        intr.target_prefix = Some("aarch64".to_string());
+        let sigs = intr.signatures(&[128]).unwrap();
+        assert_eq!(sigs[0].suffix, ".v16i8.p0v16i8");
+        assert_eq!(sigs[0].rust(),
+                   "(a: *const ::simdty::i8x16) -> (::simdty::i8x16, ::simdty::i8x16)");
+        assert_eq!(sigs.len(), 6);
+
+        intr.ret = vec![LLVMType::MatchedType(1, MatchStyle::Direct)];
+        assert_eq!(intr.signatures(&[128]),
+                   Err("`match<1>` matches a missing overloaded type".to_string()));
+    }
+
+    #[test]
+    fn address_spaces() {
+        assert_eq!(rust("llvm_global_i8ptr_ty"), None);
+        assert_eq!(rust("llvm_shared_i64ptr_ty"), None);
+        assert_eq!(rust("llvm_ptr_ty"), Some("*mut i8".to_string()));
+        let constant = LLVMType::from_ast(&ty("LLVMQualPointerType",
+                                              vec![ast::Val::Type(ty("llvm_float_ty", vec![])),
+                                                   ast::Val::Int(4)])).unwrap();
+        assert_eq!(constant.to_string(), "float addrspace(4)*");
+        assert_eq!(constant.to_concrete_rust_string(), None);
+    }
+
+    #[test]
+    fn read_only_pointers() {
+        use super::IntrinsicProperty::*;
+        use super::ptr;
+        let i8ptr = || ptr(LLVMType::Int(Some(8)));
+        let mut intr = intrinsic("int_memcpy_element_unordered_atomic",
+                                 vec![i8ptr(), i8ptr(), LLVMType::Int(Some(64)),
+                                      LLVMType::Int(Some(32))],
+                                 vec![],
+                                 vec![ArgMemOnly, NoCapture(0), NoCapture(1),
+                                      WriteOnly(0), ReadOnly(1), ImmArg(3)]);
+        let rust_sig = |intr: &Intrinsic| intr.signatures(&[]).unwrap()[0].rust();
+        assert_eq!(rust_sig(&intr), "(a: *mut i8, b: *const i8, c: i64, d: i32) -> ()");
+        assert_eq!(intr.params_with(NoCapture), [0, 1]);
+        assert_eq!(intr.params_with(NoAlias), []);
+
+        // everything is only read by an intrinsic that only reads memory,
+        // unless it says otherwise
+        intr.properties = vec![ReadMem, WriteOnly(0)];
+        assert_eq!(rust_sig(&intr), "(a: *mut i8, b: *const i8, c: i64, d: i32) -> ()");
+        intr.properties = vec![];
+        assert_eq!(rust_sig(&intr), "(a: *mut i8, b: *mut i8, c: i64, d: i32) -> ()");
+
+        // only the outer pointer is read-only
+        intr.params = vec![ptr(i8ptr())];
+        intr.properties = vec![ReadArgMem];
+        assert_eq!(rust_sig(&intr), "(a: *const *mut i8) -> ()");
+    }
+
+    #[test]
+    fn x86_extensions() {
+        let module = |name| super::x86_extension(name).map(|e| e.module);
+        assert_eq!(module("sse_max_ps"), Some("sse"));
+        assert_eq!(module("sse2_add_sd"), Some("sse2"));
+        assert_eq!(module("avx512_sqrt_ps_512"), Some("avx512f"));
+        assert_eq!(module("avx512_rcp28_ps"), Some("avx512er"));
+        assert_eq!(module("avx512_psllv_w_512"), Some("avx512bw"));
+        assert_eq!(module("avx512_mask_range_pd_512"), Some("avx512dq"));
+        assert_eq!(module("avx512_conflict_d_512"), Some("avx512cd"));
+        assert_eq!(module("avx512_permvar_qi_512"), Some("avx512vbmi"));
+        assert_eq!(module("avx512fp16_add_ph_512"), Some("avx512fp16"));
+        let feature = |name| super::x86_extension(name).map(|e| e.feature);
+        assert_eq!(feature("pclmulqdq"), Some("pclmulqdq"));
+        assert_eq!(feature("rdrand_32"), Some("rdrand"));
+        assert_eq!(module("bmi_pdep_32"), Some("bmi2"));
+        assert_eq!(module("rdtsc"), None);
+    }
+
+    #[test]
+    fn arches() {
+        assert_eq!("wasm".parse(), Ok(Arch::Wasm));
+        assert_eq!("s390".parse(), Ok(Arch::S390));
+        assert_eq!("memcpy".parse::<Arch>(), Err(()));
+        assert_eq!(Arch::Riscv.cfg_attr(),
+                   "#[cfg(any(feature = \"arch-riscv\", \
+                    target_arch = \"riscv32\", target_arch = \"riscv64\"))]");
+        assert_eq!(Arch::Ve.cfg_attr(), "#[cfg(any(feature = \"arch-ve\"))]");
+    }
+
+    #[test]
+    fn target_prefix() {
+        use std::rc::Rc;
+
+        fn def(name: &str, prefix: Option<&str>) -> ast::Def {
+            let source = ast::Source { name: "<test>".to_string(), text: String::new() };
+            ast::Def {
+                name: name.to_string(),
+                name_parts: vec![],
+                inherits: vec![],
+                body: ast::Body::default(),
+                lets: vec![],
+                fields: prefix.into_iter()
+                    .map(|p| ("TargetPrefix".to_string(), ast::Val::String(p.to_string())))
+                    .collect(),
+                span: ast::Span { source: Rc::new(source), line: 1, col: 1 },
+            }
+        }
+        // the `let` wins over the name
+        assert_eq!(super::target_prefix(&def("int_rdpid", Some("x86"))), Some("x86".to_string()));
+        assert_eq!(super::target_prefix(&def("int_csky_foo", Some("csky"))),
+                   Some("csky".to_string()));
+        // falling back to the name for older .td files
+        assert_eq!(super::target_prefix(&def("int_x86_sse_max_ps", None)),
+                   Some("x86".to_string()));
+        assert_eq!(super::target_prefix(&def("int_memcpy", Some(""))), None);
+    }
 
 }
@@ -0,0 +1,128 @@
+//! A machine-readable catalogue of the generated bindings.
+
+use std::io;
+use std::io::prelude::*;
+
+use intrinsic::Arch;
+use Binding;
+
+fn string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+fn optional(s: Option<&str>) -> String {
+    s.map(string).unwrap_or_else(|| "null".to_string())
+}
+
+fn list<T: ToString>(xs: &[T]) -> String {
+    let elems = xs.iter().map(|x| string(&x.to_string())).collect::<Vec<_>>();
+    format!("[{}]", elems.connect(", "))
+}
+
+/// Writes a JSON array with an object describing each binding in
+/// `modules`, in the same order as the Rust output.
+pub fn write_catalogue(out: &mut Write,
+                       modules: &[(Option<Arch>, Vec<Binding>)]) -> io::Result<()> {
+    try!(write!(out, "["));
+    let mut first = true;
+    for &(arch, ref bindings) in modules.iter() {
+        for b in bindings.iter() {
+            try!(write!(out, "{}\n  {{\n", if first {""} else {","}));
+            first = false;
+
+            let fields = [
+                ("path", string(&b.path())),
+                ("link_name", string(&b.link_name)),
+                ("gcc_name", optional(b.intr.gcc_name.as_ref().map(|s| &**s))),
+                ("arch", optional(arch.map(|a| a.as_str()))),
+                ("suffix", string(&b.sig.suffix)),
+                ("params", list(&b.sig.params)),
+                ("ret", list(&b.sig.ret)),
+                ("properties", list(&b.intr.properties)),
+                ("immediates", format!("[{}]", b.intr.immediate_params().iter()
+                                        .map(|i| i.to_string())
+                                        .collect::<Vec<_>>()
+                                        .connect(", "))),
+                ("docs", string(&b.docs.connect("\n"))),
+            ];
+            for (i, &(name, ref value)) in fields.iter().enumerate() {
+                try!(write!(out, "    {}: {}{}\n",
+                            string(name), value,
+                            if i + 1 == fields.len() {""} else {","}));
+            }
+            try!(write!(out, "  }}"));
+        }
+    }
+    write!(out, "\n]\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use intrinsic::{Intrinsic, IntrinsicProperty, LLVMType, Signature};
+    use Binding;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(super::string("a\"b\\c\nd\te\u{1}f"), r#""a\"b\\c\nd\u0009e\u0001f""#);
+        assert_eq!(super::optional(None), "null");
+    }
+
+    #[test]
+    fn catalogue() {
+        let intr = Intrinsic {
+            arch: None,
+            target_prefix: None,
+            name: "int_foo".to_string(),
+            gcc_name: Some("__builtin_\"foo\"\\".to_string()),
+            llvm_name: None,
+            params: vec![LLVMType::Int(Some(32))],
+            ret: vec![LLVMType::Float(Some(32))],
+            properties: vec![IntrinsicProperty::ImmArg(0)],
+        };
+        let binding = Binding {
+            intr: &intr,
+            sig: Signature {
+                suffix: String::new(),
+                params: intr.params.clone(),
+                ret: intr.ret.clone(),
+                rust_params: vec!["a: i32".to_string()],
+                rust_ret: "f32".to_string(),
+            },
+            extension: None,
+            link_name: "llvm.foo".to_string(),
+            fn_name: "foo".to_string(),
+            docs: vec!["The `llvm.foo` intrinsic; \"quoted\".".to_string(),
+                       String::new(),
+                       "A\ttab, a \\ and an \u{1b} escape.".to_string()],
+        };
+        let mut out = vec![];
+        super::write_catalogue(&mut out, &[(None, vec![binding])]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#"[
+  {
+    "path": "foo",
+    "link_name": "llvm.foo",
+    "gcc_name": "__builtin_\"foo\"\\",
+    "arch": null,
+    "suffix": "",
+    "params": ["i32"],
+    "ret": ["float"],
+    "properties": ["ImmArg<0>"],
+    "immediates": [0],
+    "docs": "The `llvm.foo` intrinsic; \"quoted\".\n\nA\u0009tab, a \\ and an \u001b escape."
+  }
+]
+"#);
+    }
+}
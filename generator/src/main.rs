@@ -1,11 +1,16 @@
-#![feature(plugin, slice_patterns, str_char)]
+#![feature(plugin, slice_patterns, str_char, fs_canonicalize)]
 #![plugin(regex_macros)]
 extern crate regex;
 
+use std::env;
+use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::collections::{BTreeMap, btree_map};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use intrinsic::{Arch, Intrinsic, IntrinsicProperty, Signature, Skipped, X86Extension};
 
 macro_rules! try_opt {
     ($e: expr) => {  match $e { Some(x) => x, None => { return None } } }
@@ -13,22 +18,78 @@ macro_rules! try_opt {
 
 mod intrinsic;
 mod ast;
+mod json;
+mod options;
 fn main() {
-    let mut s = String::new();
-    io::stdin().read_to_string(&mut s).unwrap();
+    let args = env::args().collect::<Vec<_>>();
+    let opts = match options::parse(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            let _ = writeln!(io::stderr(), "error: {}\n\n{}", msg, options::usage(&args[0]));
+            process::exit(2)
+        }
+    };
+    if opts.help {
+        println!("{}", options::usage(&args[0]));
+        return
+    }
 
-    let ast = ast::parse(&s, Path::new(""));
-    let (classes, mut defs) = ast::flatten_separate(ast);
-    let class_names = ast::classes_by_name(&classes);
-    ast::resolve_classes(&mut defs, &class_names);
+    let stdin = [PathBuf::from("-")];
+    let inputs = if opts.inputs.is_empty() { &stdin[..] } else { &opts.inputs[..] };
+    let mut ast = vec![];
+    for path in inputs.iter() {
+        let items = if path.to_str() == Some("-") {
+            let mut s = String::new();
+            io::stdin().read_to_string(&mut s).unwrap();
+            ast::parse(&s, Path::new(""), &opts.include_dirs, &opts.defines)
+        } else {
+            ast::parse_file(path, &opts.include_dirs, &opts.defines)
+        };
+        match items {
+            Ok(items) => ast.extend(items.into_iter()),
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "error: {}", e);
+                process::exit(1)
+            }
+        }
+    }
+    let defs = match ast::evaluate(&ast) {
+        Ok(defs) => defs,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "error: {}", e);
+            process::exit(1)
+        }
+    };
 
     let mut modules = BTreeMap::new();
+    let mut skipped = vec![];
     for d in defs.iter() {
         let intr = match intrinsic::Intrinsic::from_ast(d) {
             None if !d.name.starts_with("int_") => continue,
-            None => panic!("failed to parse: {:?}", d),
+            None => {
+                skipped.push(Skipped {
+                    name: d.name.clone(),
+                    suffix: String::new(),
+                    arch: intrinsic::target_prefix(d).and_then(|p| p.parse().ok()),
+                    reason: match intrinsic::unrecognised_type(d) {
+                        Some(ty) => format!("unrecognised type `{}`", ty),
+                        None => "could not understand the definition".to_string(),
+                    },
+                });
+                continue
+            }
             Some(intr) => intr
         };
+        // intrinsics for targets without an `Arch` aren't generic
+        if let (None, Some(prefix)) = (intr.arch, intr.target_prefix.as_ref()) {
+            skipped.push(Skipped {
+                name: intr.name.clone(),
+                suffix: String::new(),
+                arch: None,
+                reason: format!("unknown target prefix `{}`", prefix),
+            });
+            continue
+        }
 
         (match modules.entry(intr.arch) {
             btree_map::Entry::Occupied(o) => o.into_mut(),
@@ -36,10 +97,233 @@ fn main() {
         }).push(intr)
     }
 
+    let modules = modules.iter()
+        .filter(|&(module, _)| opts.arches.is_empty() || opts.arches.contains(module))
+        .map(|(module, items)| {
+            (*module, bindings(*module, items, &opts.vector_widths, &mut skipped))
+        })
+        .collect::<Vec<_>>();
+
+    skipped.retain(|s| opts.arches.is_empty() || opts.arches.contains(&s.arch));
+    skipped.sort_by(|a, b| (a.arch, &a.name, &a.suffix).cmp(&(b.arch, &b.name, &b.suffix)));
+    match opts.skip_report {
+        Some(ref path) => {
+            File::create(path)
+                .and_then(|mut f| write_skip_report(&mut f, &skipped, &modules, true))
+                .unwrap()
+        }
+        None if !skipped.is_empty() => {
+            write_skip_report(&mut io::stderr(), &skipped, &modules, false).unwrap()
+        }
+        None => {}
+    }
+
+    let mut out = vec![];
+    match opts.format {
+        options::Format::Rust => print_rust(&mut out, &modules),
+        options::Format::Json => json::write_catalogue(&mut out, &modules),
+    }.unwrap();
+
+    let output = opts.output_path();
+    match output {
+        Some(ref path) if opts.check => {
+            let mut existing = vec![];
+            let _ = File::open(path).and_then(|mut f| f.read_to_end(&mut existing));
+            if existing != out {
+                let _ = writeln!(io::stderr(), "`{}` is not up to date", path.display());
+                process::exit(1)
+            }
+        }
+        Some(ref path) => File::create(path).and_then(|mut f| f.write_all(&out)).unwrap(),
+        None => io::stdout().write_all(&out).unwrap(),
+    }
+}
+
+/// A single generated function declaration.
+pub struct Binding<'a> {
+    pub intr: &'a Intrinsic,
+    pub sig: Signature,
+    pub extension: Option<&'static X86Extension>,
+    pub link_name: String,
+    pub fn_name: String,
+    pub docs: Vec<String>,
+}
+
+impl<'a> Binding<'a> {
+    /// The path of the module containing this binding, relative to
+    /// the crate root, e.g. `x86::sse`.
+    pub fn module_path(&self) -> String {
+        match (self.intr.arch, self.extension) {
+            (None, _) => String::new(),
+            (Some(arch), None) => arch.to_string(),
+            (Some(arch), Some(ext)) => format!("{}::{}", arch, ext.module),
+        }
+    }
+
+    /// The path of the function, relative to the crate root.
+    pub fn path(&self) -> String {
+        let module = self.module_path();
+        if module.is_empty() {
+            self.fn_name.clone()
+        } else {
+            format!("{}::{}", module, self.fn_name)
+        }
+    }
+}
+
+/// Creates a binding for every signature of each of `items`, which
+/// are all in `module`, instantiating overloaded vectors at `widths`.
+/// Intrinsics without any, and the instantiations dropped from those
+/// with some, are added to `skipped`.
+fn bindings<'a>(module: Option<Arch>, items: &'a [Intrinsic],
+                widths: &[u32], skipped: &mut Vec<Skipped>) -> Vec<Binding<'a>> {
+    let mut ret = vec![];
+    for intr in items.iter() {
+        let mut dropped = vec![];
+        let sigs = match intr.signatures_and_dropped(widths, &mut dropped) {
+            Ok(sigs) => sigs,
+            Err(reason) => {
+                skipped.push(Skipped {
+                    name: intr.name.clone(),
+                    suffix: String::new(),
+                    arch: intr.arch,
+                    reason: reason,
+                });
+                continue
+            }
+        };
+        for (suffix, reason) in dropped.into_iter() {
+            skipped.push(Skipped {
+                name: intr.name.clone(),
+                suffix: suffix,
+                arch: intr.arch,
+                reason: reason,
+            });
+        }
+
+        for sig in sigs.into_iter() {
+            let mut link_name = intr.llvm_name.clone()
+                .unwrap_or_else(|| format!("llvm.{}",
+                                           intr.name["int_".len()..].replace("_", ".")));
+            link_name.push_str(&sig.suffix);
+
+            let raw_name = format!("{}{}", intr.short_name(), sig.suffix.replace(".", "_"));
+            let fn_name = rust_ident(&raw_name);
+            // x86 intrinsics are split into a submodule per extension
+            let extension = match module {
+                Some(Arch::X86) => intrinsic::x86_extension(&raw_name),
+                _ => None
+            };
+            let mut docs = format!("The `{}` intrinsic", link_name);
+            if let Some(ref name) = intr.gcc_name {
+                docs.push_str("; known as `");
+                docs.push_str(&name);
+                docs.push_str("` in GCC");
+            }
+            docs.push_str(".");
+
+            let mut docs = vec![docs];
+            if let Some(summary) = intr.memory_summary() {
+                docs.push(String::new());
+                docs.push(summary.to_string());
+            }
+            let no_capture = intr.params_with(IntrinsicProperty::NoCapture);
+            if !no_capture.is_empty() {
+                docs.push(String::new());
+                docs.push(format!("Does not capture {}.", param_names(&no_capture)));
+            }
+            let no_alias = intr.params_with(IntrinsicProperty::NoAlias);
+            if !no_alias.is_empty() {
+                docs.push(String::new());
+                docs.push(format!("The memory accessed through {} must not be accessed \
+                                   through any other pointer during the call.",
+                                  param_names(&no_alias)));
+            }
+            let immediates = intr.immediate_params();
+            if !immediates.is_empty() {
+                docs.push(String::new());
+                docs.push(format!("{} must be {}; passing a runtime value is a compile error.",
+                                  param_names(&immediates),
+                                  if immediates.len() == 1 {"a compile-time constant"}
+                                  else {"compile-time constants"}));
+            }
+            if !intr.properties.is_empty() {
+                let props = intr.properties.iter()
+                    .map(|p| format!("`{}`", p))
+                    .collect::<Vec<_>>();
+                docs.push(String::new());
+                docs.push(format!("Properties: {}.", props.connect(", ")));
+            }
+
+            ret.push(Binding {
+                intr: intr,
+                sig: sig,
+                extension: extension,
+                link_name: link_name,
+                fn_name: fn_name,
+                docs: docs,
+            });
+        }
+    }
+    ret
+}
+
+/// Writes the number of intrinsics skipped in each module, and of
+/// instantiations dropped from the others, and, if `full`, each of
+/// them with the reason.
+fn write_skip_report(out: &mut Write, skipped: &[Skipped],
+                     modules: &[(Option<Arch>, Vec<Binding>)], full: bool) -> io::Result<()> {
+    fn name(arch: Option<Arch>) -> &'static str {
+        arch.map_or("generic", |a| a.as_str())
+    }
+
+    if full {
+        for s in skipped.iter() {
+            try!(writeln!(out, "{}{} ({}): {}", s.name, s.suffix, name(s.arch), s.reason));
+        }
+        if !skipped.is_empty() {
+            try!(writeln!(out, ""));
+        }
+    }
 
-    println!("\
-#![feature(simd, simd_ffi, link_llvm_intrinsics)]
-#![allow(non_snake_case)]
+    let mut arches = modules.iter().map(|&(arch, _)| arch).collect::<Vec<_>>();
+    for s in skipped.iter() {
+        if !arches.contains(&s.arch) { arches.push(s.arch) }
+    }
+    arches.sort();
+    for &arch in arches.iter() {
+        let count = skipped.iter().filter(|s| s.arch == arch && s.suffix.is_empty()).count();
+        let dropped = skipped.iter().filter(|s| s.arch == arch && !s.suffix.is_empty()).count();
+        if count == 0 && dropped == 0 && !full { continue }
+
+        // each intrinsic has a run of bindings, one per signature
+        let mut generated = modules.iter()
+            .filter(|&&(a, _)| a == arch)
+            .flat_map(|&(_, ref bindings)| bindings.iter().map(|b| &b.intr.name))
+            .collect::<Vec<_>>();
+        generated.dedup();
+        try!(write!(out, "{}: skipped {} of {} intrinsics",
+                    name(arch), count, count + generated.len()));
+        if dropped > 0 {
+            try!(write!(out, ", and {} instantiations of the others", dropped));
+        }
+        try!(writeln!(out, ""));
+    }
+    Ok(())
+}
+
+fn print_rust(out: &mut Write, modules: &[(Option<Arch>, Vec<Binding>)]) -> io::Result<()> {
+    // `ops` calls the bindings at the top level
+    const OPS_DOCS: &'static str = "\
+//! The [`ops`](ops/index.html) module provides safe, generic traits
+//! (such as `Sqrt` and `Ctpop`) over some of the overloaded
+//! intrinsics at the top level, e.g. `sqrt_f32` and `sqrt_v4f32`.
+//!
+";
+    let generic = modules.iter().any(|&(module, _)| module.is_none());
+    try!(writeln!(out, "\
+#![feature(simd_ffi, link_llvm_intrinsics, abi_unadjusted, rustc_attrs)]
+#![allow(non_snake_case, improper_ctypes)]
 
 //! Bindings to (almost) all LLVM intrinsics.
 //!
@@ -49,7 +333,7 @@ fn main() {
 //! available at the top level. These are raw bindings and absolutely
 //! zero error checking is performed.
 //!
-//! # Naming
+{ops_docs}//! # Naming
 //!
 //! The intrinsics are named entirely for their internal LLVM names,
 //! with the `llvm` prefix stripped, `.` replaced by `_` and, if it
@@ -58,10 +342,32 @@ fn main() {
 //! `::adjust_trampoline` and `llvm.x86.addcarry.u32` becomes
 //! `::x86::addcarry_u32`.
 //!
+//! The `x86` intrinsics are additionally grouped into a submodule per
+//! instruction set extension, such as `x86::sse2` or `x86::avx2`,
+//! which documents the target feature they require (e.g. `-C
+//! target-feature=+avx2`). Everything in these submodules is also
+//! re-exported directly from `x86`, e.g. `x86::sse_max_ps` and
+//! `x86::sse::sse_max_ps` are the same function.
+//!
+//! Intrinsics that return several values (such as
+//! `llvm.x86.addcarry.32`) return them as a tuple, in the order of
+//! the fields of LLVM's literal struct. Those returning a `bool` among
+//! them (such as `llvm.sadd.with.overflow`), or values of different
+//! sizes that aren't a pair of scalars, are left out, since `rustc`
+//! passes such tuples differently.
+//!
 //! Many of these intrinsics have corresponding intrinsics exposed by
 //! GCC/Clang in C/C++, these names are listed in
 //! [`gcc_names`](gcc_names/index.html).
 //!
+//! # Properties
+//!
+//! The documentation of each intrinsic lists the properties LLVM
+//! records for it (such as `IntrNoMem` or `ReadOnly<1>`), along with
+//! a summary of how it accesses memory, where that is known, and which
+//! pointer arguments it does not capture or alias. Pointer arguments
+//! that are only read through are `*const`.
+//!
 //! # Platform support
 //!
 //! An intrinsic being available in a certain module (or at the top
@@ -72,93 +378,308 @@ fn main() {
 //!
 //! Using an intrinsic in a configuration that is not supported will
 //! likely cause LLVM assertions or general badness along those lines.
+//!
+//! Arguments that LLVM requires to be immediates (marked `ImmArg`)
+//! are const generic parameters, which can also be passed in their
+//! usual position, e.g. `x86::vcvtps2ph_128(a, 0)` or
+//! `x86::vcvtps2ph_128::<0>(a)`: passing anything other than a
+//! constant to them is a compile error, rather than an LLVM crash.
+//! Those taking floats or pointers as immediates are left out.
+//!
+//! Each architecture module is only compiled when building for a
+//! matching `target_arch`, or when the corresponding `arch-...` cargo
+//! feature (e.g. `arch-x86`, `arch-nvvm`) is enabled; the
+//! `all-arches` feature enables every module. Modules for targets
+//! that Rust doesn't support (such as `dx` and `ve`) are only compiled
+//! with their feature.
 
 extern crate simdty;
-");
+{ops}",
+                  ops_docs = if generic {OPS_DOCS} else {""},
+                  ops = if generic {"\npub mod ops;\n"} else {""}));
 
     let mut gcc_reexports = BTreeMap::new();
-    for (module, items) in modules.iter() {
-        let (indent, close, strip) = match *module {
-            None => {
-                println!("extern {{");
-                ("    ",
-                 "}",
-                 "int_".len())
+    for &(module, ref bindings) in modules.iter() {
+        let mut direct = vec![];
+        let mut extensions = BTreeMap::new();
+        for b in bindings.iter() {
+            if let Some(ref name) = b.intr.gcc_name {
+                (match gcc_reexports.entry(&**name) {
+                    btree_map::Entry::Occupied(o) => o.into_mut(),
+                    btree_map::Entry::Vacant(v) => v.insert(vec![])
+                }).push((b.module_path(), &*b.fn_name))
             }
+            match b.extension {
+                None => direct.push(b),
+                Some(ext) => (match extensions.entry(ext.module) {
+                    btree_map::Entry::Occupied(o) => o.into_mut(),
+                    btree_map::Entry::Vacant(v) => v.insert(vec![])
+                }).push(b)
+            }
+        }
+
+        match module {
+            None => try!(print_bindings(out, "", &direct)),
             Some(arch) => {
-                println!("\
+                try!(writeln!(out, "\
 /// LLVM intrinsics for the {arch} architecture.
-pub mod {arch} {{
-    extern {{", arch=arch);
-                ("        ",
-                 "    }\n}",
-                 "int_".len() + arch.as_str().len() + 1)
+{cfg}
+pub mod {arch} {{", arch=arch, cfg=arch.cfg_attr()));
+                let exts = intrinsic::X86_EXTENSIONS.iter()
+                    .filter(|ext| extensions.contains_key(ext.module))
+                    .collect::<Vec<_>>();
+                for ext in exts.iter() {
+                    try!(writeln!(out, "    pub use self::{}::*;", ext.module));
+                }
+                try!(print_bindings(out, "    ", &direct));
+                for ext in exts.iter() {
+                    // AVX-512 instructions on 128- and 256-bit vectors
+                    // are a separate extension
+                    let vl = if ext.module.starts_with("avx512") {
+                        "\n    ///\n    /// The forms on 128- and 256-bit vectors also require `avx512vl`."
+                    } else {
+                        ""
+                    };
+                    try!(writeln!(out, "    /// Intrinsics from the `{module}` x86 extension.
+    ///
+    /// These require the `{feature}` target feature, e.g. `-C target-feature=+{feature}`.{vl}
+    pub mod {module} {{", module=ext.module, feature=ext.feature, vl=vl));
+                    try!(print_bindings(out, "        ", &extensions[ext.module]));
+                    try!(writeln!(out, "    }}"));
+                }
+                try!(writeln!(out, "}}"));
             }
-        };
-
-        for intr in items.iter() {
-            let sigs = intr.signatures();
-
-            for &(ref suffix, ref sig) in sigs.iter() {
-
-                let mut link_name = intr.llvm_name.clone()
-                    .unwrap_or_else(|| format!("llvm.{}",
-                                               intr.name["int_".len()..].replace("_", ".")));
-                link_name.push_str(&suffix);
+        }
+    }
 
-                let raw_name = format!("{}{}", &intr.name[strip..], suffix.replace(".", "_"));
-                let fn_name = avoid_keywords(&raw_name);
-                let mut docs = format!("The `{}` intrinsic", link_name);
-                if let Some(ref name) = intr.gcc_name {
-                    docs.push_str("; known as `");
-                    docs.push_str(&name);
-                    docs.push_str("` in GCC");
+    try!(writeln!(out, "\
+/// Listing of the corresponding name(s) of many GCC intrinsics, for reference/search purposes.
+///
+/// <dl>"));
+    for (gcc_name, locations) in gcc_reexports.iter() {
+        try!(writeln!(out, "/// <dt><strong><code>{}</code></strong></dt>", gcc_name));
+        for &(ref path, fn_name) in locations.iter() {
+            try!(writeln!(out, "\
+/// <dd><a href=\"../{url}{url_sep}fn.{fn_name}.html\"><code>{path}{mod_sep}{fn_name}</code></a></dd>",
+                          url = path.replace("::", "/"),
+                          path = path,
+                          url_sep = if path.is_empty() {""} else {"/"},
+                          mod_sep = if path.is_empty() {""} else {"::"},
+                          fn_name = fn_name))
+        }
+    }
+    try!(writeln!(out, "\
+/// </dl>
+pub mod gcc_names {{}}"));
+    Ok(())
+}
 
-                    (match gcc_reexports.entry(&*name) {
-                        btree_map::Entry::Occupied(o) => o.into_mut(),
-                        btree_map::Entry::Vacant(v) => v.insert(vec![])
-                    }).push((*module, fn_name.to_string()))
-                }
-                docs.push_str(".");
+/// Writes `bindings`: `extern` blocks declaring those without
+/// immediate parameters (`"C"` for variadic ones, since it's the only
+/// ABI allowing `...`), and a wrapper for each of the others, which
+/// takes the immediates as const generics.
+fn print_bindings(out: &mut Write, indent: &str, bindings: &[&Binding]) -> io::Result<()> {
+    let (wrapped, direct): (Vec<&Binding>, Vec<&Binding>) = bindings.iter()
+        .cloned()
+        .partition(|b| !b.intr.immediate_params().is_empty());
+    let (variadic, direct): (Vec<&Binding>, Vec<&Binding>) = direct.into_iter()
+        .partition(|b| b.sig.rust_params.last().map_or(false, |p| p == "..."));
 
-                println!("\
-{indent}/// {docs}
+    let inner = format!("{}    ", indent);
+    for &(abi, ref bindings) in [("unadjusted", direct), ("C", variadic)].iter() {
+        if bindings.is_empty() { continue }
+        try!(writeln!(out, "{}extern \"{}\" {{", indent, abi));
+        for b in bindings.iter() {
+            try!(print_docs(out, &inner, b));
+            try!(writeln!(out, "\
 {indent}#[link_name = \"{link_name}\"]
 {indent}pub fn {fn_name}{sig};",
-                         indent = indent,
-                         docs = docs,
-                         link_name = link_name,
-                         fn_name = fn_name,
-                         sig = sig);
-            }
+                          indent = inner,
+                          link_name = b.link_name,
+                          fn_name = b.fn_name,
+                          sig = b.sig.rust()));
         }
+        try!(writeln!(out, "{}}}", indent));
+    }
 
-        println!("{}", close);
+    for b in wrapped.iter() {
+        let immediates = b.intr.immediate_params();
+        let indices = immediates.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut consts = vec![];
+        let mut params = vec![];
+        let mut args = vec![];
+        for (i, param) in b.sig.rust_params.iter().enumerate() {
+            let name = intrinsic::param_name(i);
+            if immediates.contains(&i) {
+                let upper = name.to_uppercase().collect::<String>();
+                let ty = &param[param.find(": ").unwrap() + 2..];
+                consts.push(format!("const {}: {}", upper, ty));
+                args.push(upper);
+            } else {
+                params.push(&**param);
+                args.push(name.to_string());
+            }
+        }
+        try!(print_docs(out, indent, b));
+        try!(writeln!(out, "\
+{indent}#[inline(always)]
+{indent}#[rustc_legacy_const_generics({indices})]
+{indent}pub unsafe fn {fn_name}<{consts}>({params}) -> {ret} {{
+{indent}    extern \"unadjusted\" {{
+{indent}        #[link_name = \"{link_name}\"]
+{indent}        fn {fn_name}{sig};
+{indent}    }}
+{indent}    {fn_name}({args})
+{indent}}}",
+                      indent = indent,
+                      indices = indices.connect(", "),
+                      fn_name = b.fn_name,
+                      consts = consts.connect(", "),
+                      params = params.connect(", "),
+                      ret = b.sig.rust_ret,
+                      link_name = b.link_name,
+                      sig = b.sig.rust(),
+                      args = args.connect(", ")));
     }
+    Ok(())
+}
 
-    println!("\
-/// Listing of the corresponding name(s) of many GCC intrinsics, for reference/search purposes.
-///
-/// <dl>");
-    for (gcc_name, locations) in gcc_reexports.iter() {
-        println!("/// <dt><strong><code>{}</code></strong></dt>", gcc_name);
-        for &(module, ref fn_name) in locations.iter() {
-            println!("\
-/// <dd><a href=\"../{module}{url_sep}fn.{fn_name}.html\"><code>{module}{mod_sep}{fn_name}</code></a></dd>",
-                     module = module.map(|a| a.as_str()).unwrap_or(""),
-                     url_sep = if module.is_some() {"/"} else {""},
-                     mod_sep = if module.is_some() {"::"} else {""},
-                     fn_name = fn_name)
+fn print_docs(out: &mut Write, indent: &str, b: &Binding) -> io::Result<()> {
+    for line in b.docs.iter() {
+        if line.is_empty() {
+            try!(writeln!(out, "{}///", indent));
+        } else {
+            try!(writeln!(out, "{}/// {}", indent, line));
         }
     }
-println!("\
-/// </dl>
-pub mod gcc_names {{}}");
+    Ok(())
 }
 
-fn avoid_keywords(s: &str) -> &str {
+/// `s` as a Rust identifier: keywords get a trailing `_`, and names
+/// starting with a digit (like `3dnow_pavgusb`) a leading one.
+fn rust_ident(s: &str) -> String {
     match s {
-        "in" => "in_",
-        _ => s
+        "in" => "in_".to_string(),
+        _ if s.chars().next().map_or(false, |c| c.is_digit(10)) => format!("_{}", s),
+        _ => s.to_string()
+    }
+}
+
+/// The names of the parameters at the (zero-based) indices `params`,
+/// for the docs, e.g. "`a`, `c`".
+fn param_names(params: &[usize]) -> String {
+    params.iter()
+        .map(|&i| format!("`{}`", intrinsic::param_name(i)))
+        .collect::<Vec<_>>()
+        .connect(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use intrinsic::{Arch, Intrinsic, IntrinsicProperty, LLVMType, Signature, Skipped};
+    use Binding;
+
+    fn intrinsic(name: &str, arch: Option<Arch>, params: Vec<LLVMType>,
+                 properties: Vec<IntrinsicProperty>) -> Intrinsic {
+        Intrinsic {
+            arch: arch,
+            target_prefix: arch.map(|a| a.as_str().to_string()),
+            name: name.to_string(),
+            gcc_name: None,
+            llvm_name: None,
+            params: params,
+            ret: vec![],
+            properties: properties,
+        }
+    }
+
+    fn binding<'a>(intr: &'a Intrinsic, suffix: &str, fn_name: &str,
+                   params: &[&str], ret: &str) -> Binding<'a> {
+        Binding {
+            intr: intr,
+            sig: Signature {
+                suffix: suffix.to_string(),
+                params: intr.params.clone(),
+                ret: intr.ret.clone(),
+                rust_params: params.iter().map(|p| p.to_string()).collect(),
+                rust_ret: ret.to_string(),
+            },
+            extension: None,
+            link_name: format!("llvm.foo{}", suffix),
+            fn_name: fn_name.to_string(),
+            docs: vec!["The `llvm.foo` intrinsic.".to_string()],
+        }
+    }
+
+    #[test]
+    fn const_generics() {
+        let plain = intrinsic("int_foo", None, vec![LLVMType::Int(Some(32))], vec![]);
+        let plain = binding(&plain, "", "foo", &["a: i32"], "i32");
+        let intr = intrinsic("int_foo", None,
+                             vec![LLVMType::Int(Some(32)), LLVMType::Int(Some(8)),
+                                  LLVMType::Int(Some(1))],
+                             vec![IntrinsicProperty::ImmArg(2), IntrinsicProperty::ImmArg(1)]);
+        let immediate = binding(&intr, "", "bar", &["a: i32", "b: i8", "c: bool"], "()");
+        let mut out = vec![];
+        super::print_bindings(&mut out, "", &[&plain, &immediate]).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+extern \"unadjusted\" {
+    /// The `llvm.foo` intrinsic.
+    #[link_name = \"llvm.foo\"]
+    pub fn foo(a: i32) -> i32;
+}
+/// The `llvm.foo` intrinsic.
+#[inline(always)]
+#[rustc_legacy_const_generics(1, 2)]
+pub unsafe fn bar<const B: i8, const C: bool>(a: i32) -> () {
+    extern \"unadjusted\" {
+        #[link_name = \"llvm.foo\"]
+        fn bar(a: i32, b: i8, c: bool) -> ();
+    }
+    bar(a, B, C)
+}
+");
+    }
+
+    #[test]
+    fn skip_report() {
+        let skip = |name: &str, suffix: &str, arch, reason: &str| Skipped {
+            name: name.to_string(),
+            suffix: suffix.to_string(),
+            arch: arch,
+            reason: reason.to_string(),
+        };
+        let skipped = [
+            skip("int_bar", "", None, "unknown target prefix `bar`"),
+            skip("int_x86_foo", ".v4i32", Some(Arch::X86), "unsupported type `<4 x i1>`"),
+            skip("int_x86_meta", "", Some(Arch::X86), "unsupported type `metadata`"),
+        ];
+        let intr = intrinsic("int_x86_foo", Some(Arch::X86), vec![LLVMType::Vector(None)], vec![]);
+        let modules = vec![
+            (None, vec![]),
+            (Some(Arch::X86), vec![binding(&intr, ".v16i8", "foo_v16i8", &["a: i8x16"], "()"),
+                                   binding(&intr, ".v8i16", "foo_v8i16", &["a: i16x8"], "()")]),
+            (Some(Arch::Arm), vec![]),
+        ];
+
+        let report = |full| {
+            let mut out = vec![];
+            super::write_skip_report(&mut out, &skipped, &modules, full).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(report(true), "\
+int_bar (generic): unknown target prefix `bar`
+int_x86_foo.v4i32 (x86): unsupported type `<4 x i1>`
+int_x86_meta (x86): unsupported type `metadata`
+
+generic: skipped 1 of 1 intrinsics
+arm: skipped 0 of 0 intrinsics
+x86: skipped 1 of 2 intrinsics, and 1 instantiations of the others
+");
+        // architectures without any skipped are left out of the summary
+        assert_eq!(report(false), "\
+generic: skipped 1 of 1 intrinsics
+x86: skipped 1 of 2 intrinsics, and 1 instantiations of the others
+");
     }
 }
@@ -0,0 +1,231 @@
+//! Command line handling.
+
+use std::fs;
+use std::path::PathBuf;
+
+use intrinsic::{self, Arch};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    Rust,
+    Json,
+}
+
+impl Format {
+    /// The file written when the output path is a directory.
+    pub fn file_name(&self) -> &'static str {
+        match *self {
+            Format::Rust => "lib.rs",
+            Format::Json => "intrinsics.json",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Options {
+    /// The TableGen files to read, `-` being stdin; stdin is read if
+    /// this is empty.
+    pub inputs: Vec<PathBuf>,
+    pub include_dirs: Vec<PathBuf>,
+    /// The names to `#define` before reading the inputs.
+    pub defines: Vec<String>,
+    /// Where to write the output (a file, or a directory in which to
+    /// write `format.file_name()`); stdout if `None`.
+    pub output: Option<PathBuf>,
+    /// The modules to emit, `None` being the top level; all of them
+    /// if this is empty.
+    pub arches: Vec<Option<Arch>>,
+    pub format: Format,
+    /// Compare the output with the existing contents of `output`
+    /// rather than writing it.
+    pub check: bool,
+    pub vector_widths: Vec<u32>,
+    /// Where to write the list of intrinsics without bindings; only a
+    /// summary is printed to stderr if `None`.
+    pub skip_report: Option<PathBuf>,
+    pub help: bool,
+}
+
+impl Options {
+    /// The file to write to, if not stdout: `output`, or the file for
+    /// the format inside it if it's a directory.
+    pub fn output_path(&self) -> Option<PathBuf> {
+        self.output.as_ref().map(|path| {
+            if fs::metadata(path).map(|m| m.is_dir()).unwrap_or(false) {
+                path.join(self.format.file_name())
+            } else {
+                path.clone()
+            }
+        })
+    }
+}
+
+pub fn usage(program: &str) -> String {
+    format!("\
+Usage: {} [options] [<input.td>...]
+
+Generates Rust bindings to the LLVM intrinsics described by the given
+TableGen files (e.g. llvm/IR/Intrinsics.td), or stdin if there are none.
+
+Options:
+    -I <dir>                 search <dir> for `include`d files
+    -D <name>                `#define` <name> before reading the input
+    -o <path>                write to <path> (a file, or a directory in which
+                             to write lib.rs/intrinsics.json) instead of stdout
+    --arch <arch>            only emit the module for <arch> (e.g. x86), or
+                             `generic` for the top level; may be repeated
+    --format <rust|json>     emit Rust source (default) or a JSON catalogue
+    --check                  fail if the output path differs from what would
+                             be generated, instead of writing it
+    --vector-widths <bits>   comma-separated total sizes of the vectors used
+                             for overloaded intrinsics, of those each
+                             architecture has (default {})
+    --skip-report <path>     write the intrinsics, and instantiations of
+                             them, that have no bindings, and why, to <path>
+    -h, --help               print this message",
+            program,
+            intrinsic::DEFAULT_VECTOR_WIDTHS.iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<_>>()
+                .connect(","))
+}
+
+/// Parses the command line arguments, not including the program name.
+pub fn parse(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        inputs: vec![],
+        include_dirs: vec![],
+        defines: vec![],
+        output: None,
+        arches: vec![],
+        format: Format::Rust,
+        check: false,
+        vector_widths: intrinsic::DEFAULT_VECTOR_WIDTHS.to_vec(),
+        skip_report: None,
+        help: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        // flags with a value accept it as the next argument, or
+        // attached (`-Idir`, `--arch=x86`)
+        let (flag, attached) = if arg.starts_with("--") {
+            match arg.find('=') {
+                Some(i) => (&arg[..i], Some(&arg[i + 1..])),
+                None => (&**arg, None)
+            }
+        } else if (arg.starts_with("-I") || arg.starts_with("-D")) && arg.len() > 2 {
+            (&arg[..2], Some(&arg[2..]))
+        } else {
+            (&**arg, None)
+        };
+        let mut value = || -> Result<String, String> {
+            match attached {
+                Some(v) => Ok(v.to_string()),
+                None => args.next().cloned().ok_or(format!("`{}` requires a value", flag))
+            }
+        };
+
+        match flag {
+            "-h" | "--help" => opts.help = true,
+            "--check" => opts.check = true,
+            "-I" => opts.include_dirs.push(PathBuf::from(try!(value()))),
+            "-D" => opts.defines.push(try!(value())),
+            "-o" => opts.output = Some(PathBuf::from(try!(value()))),
+            "--skip-report" => opts.skip_report = Some(PathBuf::from(try!(value()))),
+            "--arch" => {
+                let name = try!(value());
+                let arch = match &*name {
+                    "generic" => None,
+                    name => match name.parse() {
+                        Ok(arch) => Some(arch),
+                        Err(()) => return Err(format!("unknown architecture `{}`", name))
+                    }
+                };
+                opts.arches.push(arch)
+            }
+            "--format" => {
+                opts.format = match &*try!(value()) {
+                    "rust" => Format::Rust,
+                    "json" => Format::Json,
+                    f => return Err(format!("unknown format `{}`", f))
+                }
+            }
+            "--vector-widths" => {
+                let widths = try!(value());
+                opts.vector_widths = try!(widths.split(',')
+                    .map(|w| w.trim().parse().map_err(|_| format!("invalid width `{}`", w)))
+                    .collect());
+            }
+            _ if flag.starts_with("-") && flag != "-" => {
+                return Err(format!("unknown option `{}`", flag))
+            }
+            _ => opts.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if opts.check && opts.output.is_none() {
+        return Err("`--check` requires an output path (`-o`)".to_string())
+    }
+    Ok(opts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use intrinsic::{self, Arch};
+    use super::{parse, Format, Options};
+
+    fn opts(args: &[&str]) -> Result<Options, String> {
+        parse(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn include_dirs() {
+        let o = opts(&["-I", "a", "-Ib", "x.td"]).unwrap();
+        assert_eq!(o.include_dirs, [PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(o.inputs, [PathBuf::from("x.td")]);
+
+        assert_eq!(opts(&["x.td", "-I"]).err().unwrap(), "`-I` requires a value");
+    }
+
+    #[test]
+    fn output() {
+        assert_eq!(opts(&[]).unwrap().output_path(), None);
+
+        let file = env::temp_dir().join("generator-test-output.rs");
+        let o = opts(&["-o", &file.display().to_string()]).unwrap();
+        assert_eq!(o.output_path(), Some(file.clone()));
+
+        let dir = env::temp_dir().join("generator-test-output");
+        fs::create_dir_all(&dir).unwrap();
+        let o = opts(&["-o", &dir.display().to_string()]).unwrap();
+        assert_eq!(o.output_path(), Some(dir.join("lib.rs")));
+        let o = opts(&["--format=json", "-o", &dir.display().to_string()]).unwrap();
+        assert_eq!(o.format, Format::Json);
+        assert_eq!(o.output_path(), Some(dir.join("intrinsics.json")));
+
+        assert_eq!(opts(&["--check"]).err().unwrap(),
+                   "`--check` requires an output path (`-o`)");
+    }
+
+    #[test]
+    fn arches() {
+        assert_eq!(opts(&[]).unwrap().arches, []);
+        let o = opts(&["--arch", "generic", "--arch=x86"]).unwrap();
+        assert_eq!(o.arches, [None, Some(Arch::X86)]);
+
+        assert_eq!(opts(&["--arch", "vax"]).err().unwrap(), "unknown architecture `vax`");
+    }
+
+    #[test]
+    fn vector_widths() {
+        assert_eq!(opts(&[]).unwrap().vector_widths, intrinsic::DEFAULT_VECTOR_WIDTHS);
+        let o = opts(&["--vector-widths", "64, 128"]).unwrap();
+        assert_eq!(o.vector_widths, [64, 128]);
+
+        assert_eq!(opts(&["--vector-widths=128,wide"]).err().unwrap(), "invalid width `wide`");
+    }
+}
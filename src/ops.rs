@@ -0,0 +1,267 @@
+//! Safe, generic interfaces to some overloaded target-independent
+//! intrinsics.
+//!
+//! Each trait is implemented for the scalar and `simdty` vector types
+//! with a corresponding binding at the top level of this crate, and
+//! calls it directly, e.g. `Sqrt` for `f32x4` calls
+//! [`sqrt_v4f32`](../fn.sqrt_v4f32.html). This allows generic code to
+//! be written once for all of them:
+//!
+//! ```rust
+//! extern crate llvmint;
+//! extern crate simdty;
+//! use llvmint::ops::Sqrt;
+//!
+//! fn norm<T: Sqrt>(x: T) -> T { x.sqrt() }
+//!
+//! # fn main() {
+//! assert_eq!(norm(4.0f64), 2.0);
+//! assert_eq!(norm(simdty::f32x4(1.0, 4.0, 9.0, 16.0)).3, 4.0);
+//! # }
+//! ```
+
+use std::{f32, f64};
+use simdty::{f32x2, f32x4, f32x8, f32x16, f64x2, f64x4, f64x8};
+use simdty::{i8x8, i8x16, i8x32, i8x64, i16x4, i16x8, i16x16, i16x32};
+use simdty::{i32x2, i32x4, i32x8, i32x16, i64x2, i64x4, i64x8};
+
+/// The square root of each element (`llvm.sqrt`).
+///
+/// Negative elements have a NaN square root.
+pub trait Sqrt {
+    fn sqrt(self) -> Self;
+}
+
+/// The fused multiply-add `self * b + c` of each element, with a
+/// single rounding (`llvm.fma`).
+pub trait Fma {
+    fn fma(self, b: Self, c: Self) -> Self;
+}
+
+/// The number of set bits in each element (`llvm.ctpop`).
+pub trait Ctpop {
+    fn ctpop(self) -> Self;
+}
+
+/// Each element with its bytes reversed (`llvm.bswap`).
+pub trait Bswap {
+    fn bswap(self) -> Self;
+}
+
+/// The number of leading zero bits in each element (`llvm.ctlz`).
+///
+/// This is the bit-width of the element for zero.
+pub trait Ctlz {
+    fn ctlz(self) -> Self;
+}
+
+/// The number of trailing zero bits in each element (`llvm.cttz`).
+///
+/// This is the bit-width of the element for zero.
+pub trait Cttz {
+    fn cttz(self) -> Self;
+}
+
+// `llvm.sqrt` is undefined for negative numbers (other than -0.0),
+// so they're replaced by NaN first, like `f32::sqrt`.
+macro_rules! impl_sqrt {
+    ($($ty: ident => $f: ident, $nan: expr;)*) => {
+        $(impl Sqrt for $ty {
+            #[inline]
+            fn sqrt(self) -> $ty {
+                let x = if self < 0.0 { $nan } else { self };
+                unsafe { ::$f(x) }
+            }
+        })*
+    }
+}
+macro_rules! impl_sqrt_vector {
+    ($($ty: ident => $f: ident, $nan: expr, ($($i: tt),*);)*) => {
+        $(impl Sqrt for $ty {
+            #[inline]
+            fn sqrt(self) -> $ty {
+                let x = $ty($(if self.$i < 0.0 { $nan } else { self.$i }),*);
+                unsafe { ::$f(x) }
+            }
+        })*
+    }
+}
+
+impl_sqrt! {
+    f32 => sqrt_f32, f32::NAN;
+    f64 => sqrt_f64, f64::NAN;
+}
+impl_sqrt_vector! {
+    f32x2 => sqrt_v2f32, f32::NAN, (0, 1);
+    f32x4 => sqrt_v4f32, f32::NAN, (0, 1, 2, 3);
+    f32x8 => sqrt_v8f32, f32::NAN, (0, 1, 2, 3, 4, 5, 6, 7);
+    f32x16 => sqrt_v16f32, f32::NAN,
+        (0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+    f64x2 => sqrt_v2f64, f64::NAN, (0, 1);
+    f64x4 => sqrt_v4f64, f64::NAN, (0, 1, 2, 3);
+    f64x8 => sqrt_v8f64, f64::NAN, (0, 1, 2, 3, 4, 5, 6, 7);
+}
+
+macro_rules! impl_fma {
+    ($($ty: ty => $f: ident;)*) => {
+        $(impl Fma for $ty {
+            #[inline]
+            fn fma(self, b: $ty, c: $ty) -> $ty {
+                unsafe { ::$f(self, b, c) }
+            }
+        })*
+    }
+}
+
+impl_fma! {
+    f32 => fma_f32;
+    f64 => fma_f64;
+    f32x2 => fma_v2f32;
+    f32x4 => fma_v4f32;
+    f32x8 => fma_v8f32;
+    f32x16 => fma_v16f32;
+    f64x2 => fma_v2f64;
+    f64x4 => fma_v4f64;
+    f64x8 => fma_v8f64;
+}
+
+// Unsigned integers are passed to the intrinsic for the signed type
+// of the same width. The `$extra` argument, if any, is passed after
+// `self`.
+macro_rules! impl_int_unary {
+    ($Trait: ident, $method: ident; $($ty: ty => $f: ident as $raw: ty;)*) => {
+        $(impl $Trait for $ty {
+            #[inline]
+            fn $method(self) -> $ty {
+                unsafe { ::$f(self as $raw) as $ty }
+            }
+        })*
+    };
+    ($Trait: ident, $method: ident, $extra: expr; $($ty: ty => $f: ident as $raw: ty;)*) => {
+        $(impl $Trait for $ty {
+            #[inline]
+            fn $method(self) -> $ty {
+                unsafe { ::$f(self as $raw, $extra) as $ty }
+            }
+        })*
+    }
+}
+macro_rules! impl_vector_unary {
+    ($Trait: ident, $method: ident; $($ty: ty => $f: ident;)*) => {
+        $(impl $Trait for $ty {
+            #[inline]
+            fn $method(self) -> $ty {
+                unsafe { ::$f(self) }
+            }
+        })*
+    };
+    ($Trait: ident, $method: ident, $extra: expr; $($ty: ty => $f: ident;)*) => {
+        $(impl $Trait for $ty {
+            #[inline]
+            fn $method(self) -> $ty {
+                unsafe { ::$f(self, $extra) }
+            }
+        })*
+    }
+}
+
+impl_int_unary! {
+    Ctpop, ctpop;
+    i8 => ctpop_i8 as i8; u8 => ctpop_i8 as i8;
+    i16 => ctpop_i16 as i16; u16 => ctpop_i16 as i16;
+    i32 => ctpop_i32 as i32; u32 => ctpop_i32 as i32;
+    i64 => ctpop_i64 as i64; u64 => ctpop_i64 as i64;
+}
+impl_vector_unary! {
+    Ctpop, ctpop;
+    i8x8 => ctpop_v8i8;
+    i8x16 => ctpop_v16i8;
+    i8x32 => ctpop_v32i8;
+    i8x64 => ctpop_v64i8;
+    i16x4 => ctpop_v4i16;
+    i16x8 => ctpop_v8i16;
+    i16x16 => ctpop_v16i16;
+    i16x32 => ctpop_v32i16;
+    i32x2 => ctpop_v2i32;
+    i32x4 => ctpop_v4i32;
+    i32x8 => ctpop_v8i32;
+    i32x16 => ctpop_v16i32;
+    i64x2 => ctpop_v2i64;
+    i64x4 => ctpop_v4i64;
+    i64x8 => ctpop_v8i64;
+}
+
+// `llvm.bswap` requires an even number of bytes.
+impl_int_unary! {
+    Bswap, bswap;
+    i16 => bswap_i16 as i16; u16 => bswap_i16 as i16;
+    i32 => bswap_i32 as i32; u32 => bswap_i32 as i32;
+    i64 => bswap_i64 as i64; u64 => bswap_i64 as i64;
+}
+impl_vector_unary! {
+    Bswap, bswap;
+    i16x4 => bswap_v4i16;
+    i16x8 => bswap_v8i16;
+    i16x16 => bswap_v16i16;
+    i16x32 => bswap_v32i16;
+    i32x2 => bswap_v2i32;
+    i32x4 => bswap_v4i32;
+    i32x8 => bswap_v8i32;
+    i32x16 => bswap_v16i32;
+    i64x2 => bswap_v2i64;
+    i64x4 => bswap_v4i64;
+    i64x8 => bswap_v8i64;
+}
+
+// `is_zero_undef` is false, so zero is well-defined.
+impl_int_unary! {
+    Ctlz, ctlz, false;
+    i8 => ctlz_i8 as i8; u8 => ctlz_i8 as i8;
+    i16 => ctlz_i16 as i16; u16 => ctlz_i16 as i16;
+    i32 => ctlz_i32 as i32; u32 => ctlz_i32 as i32;
+    i64 => ctlz_i64 as i64; u64 => ctlz_i64 as i64;
+}
+impl_vector_unary! {
+    Ctlz, ctlz, false;
+    i8x8 => ctlz_v8i8;
+    i8x16 => ctlz_v16i8;
+    i8x32 => ctlz_v32i8;
+    i8x64 => ctlz_v64i8;
+    i16x4 => ctlz_v4i16;
+    i16x8 => ctlz_v8i16;
+    i16x16 => ctlz_v16i16;
+    i16x32 => ctlz_v32i16;
+    i32x2 => ctlz_v2i32;
+    i32x4 => ctlz_v4i32;
+    i32x8 => ctlz_v8i32;
+    i32x16 => ctlz_v16i32;
+    i64x2 => ctlz_v2i64;
+    i64x4 => ctlz_v4i64;
+    i64x8 => ctlz_v8i64;
+}
+
+impl_int_unary! {
+    Cttz, cttz, false;
+    i8 => cttz_i8 as i8; u8 => cttz_i8 as i8;
+    i16 => cttz_i16 as i16; u16 => cttz_i16 as i16;
+    i32 => cttz_i32 as i32; u32 => cttz_i32 as i32;
+    i64 => cttz_i64 as i64; u64 => cttz_i64 as i64;
+}
+impl_vector_unary! {
+    Cttz, cttz, false;
+    i8x8 => cttz_v8i8;
+    i8x16 => cttz_v16i8;
+    i8x32 => cttz_v32i8;
+    i8x64 => cttz_v64i8;
+    i16x4 => cttz_v4i16;
+    i16x8 => cttz_v8i16;
+    i16x16 => cttz_v16i16;
+    i16x32 => cttz_v32i16;
+    i32x2 => cttz_v2i32;
+    i32x4 => cttz_v4i32;
+    i32x8 => cttz_v8i32;
+    i32x16 => cttz_v16i32;
+    i64x2 => cttz_v2i64;
+    i64x4 => cttz_v4i64;
+    i64x8 => cttz_v8i64;
+}
@@ -0,0 +1,23 @@
+extern crate llvmint;
+
+// The sources of `memcpy` and `memmove` are only read, so they take
+// `*const` pointers, e.g. from `as_ptr`, without a cast.
+#[test]
+fn memcpy_const_source() {
+    let src: [i8; 4] = [1, 2, 3, 4];
+    let mut dst = [0i8; 4];
+    unsafe { llvmint::memcpy_p0i8_p0i8_i64(dst.as_mut_ptr(), src.as_ptr(), 4, false) };
+
+    assert_eq!(dst, [1, 2, 3, 4]);
+}
+
+#[test]
+fn memmove_overlapping() {
+    let mut buf: [i8; 6] = [1, 2, 3, 4, 5, 6];
+    unsafe {
+        let src: *const i8 = buf.as_ptr();
+        llvmint::memmove_p0i8_p0i8_i64(buf.as_mut_ptr().offset(2), src, 4, false)
+    };
+
+    assert_eq!(buf, [1, 2, 1, 2, 3, 4]);
+}
@@ -0,0 +1,68 @@
+extern crate llvmint;
+extern crate simdty;
+
+use llvmint::ops::{Sqrt, Fma, Ctpop, Bswap, Ctlz, Cttz};
+use simdty::{f32x4, f32x8, f64x2, f64x8, i8x8, i8x32, i16x8, i32x4, i64x4};
+
+fn sqrt<T: Sqrt>(x: T) -> T { x.sqrt() }
+
+#[test]
+fn sqrt_generic() {
+    assert_eq!(sqrt(9.0f32), 3.0);
+    assert_eq!(sqrt(0.25f64), 0.5);
+
+    let a = sqrt(f32x4(0.0, 1.0, 4.0, -9.0));
+    assert_eq!(a.0, 0.0);
+    assert_eq!(a.1, 1.0);
+    assert_eq!(a.2, 2.0);
+    assert!(a.3.is_nan());
+
+    let b = sqrt(f64x2(16.0, -0.0));
+    assert_eq!(b.0, 4.0);
+    assert_eq!(b.1, 0.0);
+
+    assert!(Sqrt::sqrt(-1.0f64).is_nan());
+}
+
+#[test]
+fn fma() {
+    assert_eq!(Fma::fma(2.0f32, 3.0, 1.0), 7.0);
+    assert_eq!(Fma::fma(0.5f64, 4.0, -2.0), 0.0);
+}
+
+#[test]
+fn integers() {
+    assert_eq!(0b1011_0001u8.ctpop(), 4);
+    assert_eq!((-1i64).ctpop(), 64);
+    assert_eq!(0x1234u16.bswap(), 0x3412);
+    assert_eq!(0x12345678i32.bswap(), 0x78563412);
+    assert_eq!(1u32.ctlz(), 31);
+    assert_eq!(0i16.ctlz(), 16);
+    assert_eq!(8u64.cttz(), 3);
+    assert_eq!(0u8.cttz(), 8);
+
+    let a = i32x4(0, 1, 3, -1).ctpop();
+    assert_eq!((a.0, a.1, a.2, a.3), (0, 1, 2, 32));
+    let b = i16x8(1, 0x100, 0, 0, 0, 0, 0, 0).bswap();
+    assert_eq!((b.0, b.1), (0x100, 1));
+}
+
+#[test]
+fn other_widths() {
+    let a = sqrt(f32x8(1.0, 4.0, 9.0, 16.0, 25.0, 36.0, 49.0, -1.0));
+    assert_eq!((a.0, a.3, a.6), (1.0, 4.0, 7.0));
+    assert!(a.7.is_nan());
+    let b = f64x8(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0);
+    let c = b.fma(b, f64x8(-1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0));
+    assert_eq!((c.0, c.1, c.7), (0.0, 4.0, 65.0));
+
+    let d = i8x8(0, 1, 3, 7, 15, 31, 63, -1).ctpop();
+    assert_eq!((d.0, d.3, d.7), (0, 3, 8));
+    let e = i8x32(1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                  0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64).cttz();
+    assert_eq!((e.0, e.1, e.31), (0, 8, 6));
+    let f = i64x4(1, 0, 0, -1).ctlz();
+    assert_eq!((f.0, f.1, f.3), (63, 64, 0));
+    let g = i64x4(0x0102, 0, 0, 0).bswap();
+    assert_eq!(g.0, 0x0201 << 48);
+}
@@ -0,0 +1,33 @@
+extern crate llvmint;
+extern crate simdty;
+
+use simdty::{f32x8, f64x8, i8x8};
+
+#[test]
+fn sqrt_256() {
+    let a = f32x8(0.0, 1.0, 4.0, 9.0, 16.0, 25.0, 36.0, 49.0);
+    let b = unsafe { llvmint::sqrt_v8f32(a) };
+
+    assert_eq!((b.0, b.1, b.2, b.3), (0.0, 1.0, 2.0, 3.0));
+    assert_eq!((b.4, b.5, b.6, b.7), (4.0, 5.0, 6.0, 7.0));
+}
+
+#[test]
+fn fma_512() {
+    let a = f64x8(1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0);
+    let b = f64x8(2.0, 2.0, 2.0, 2.0, 0.5, 0.5, 0.5, 0.5);
+    let c = f64x8(1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0);
+    let d = unsafe { llvmint::fma_v8f64(a, b, c) };
+
+    assert_eq!((d.0, d.1, d.2, d.3), (3.0, 5.0, 7.0, 9.0));
+    assert_eq!((d.4, d.5, d.6, d.7), (1.5, 2.0, 2.5, 3.0));
+}
+
+#[test]
+fn ctpop_64() {
+    let a = i8x8(0, 1, 3, 7, 15, 31, 63, -1);
+    let b = unsafe { llvmint::ctpop_v8i8(a) };
+
+    assert_eq!((b.0, b.1, b.2, b.3), (0, 1, 2, 3));
+    assert_eq!((b.4, b.5, b.6, b.7), (4, 5, 6, 8));
+}
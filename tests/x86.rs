@@ -28,3 +28,39 @@ fn sse_sqrt() {
     assert_eq!(b.2, 2.0);
     assert_eq!(b.3, 3.0);
 }
+
+// Several return values are a tuple, in LLVM's order.
+#[test]
+fn addcarry() {
+    let (carry, sum) = unsafe { llvmint::x86::addcarry_32(1, -1, 1) };
+
+    assert_eq!((carry, sum), (1, 1));
+}
+
+// Immediates are const generics, passed either in their usual
+// position or explicitly.
+#[test]
+fn sse_cmp_immediate() {
+    let a = f32x4(1.0, 2.0, 3.0, 4.0);
+    let b = f32x4(2.0, 2.0, 2.0, 2.0);
+    let lt = unsafe { llvmint::x86::sse_cmp_ps(a, b, 1) };
+    let le = unsafe { llvmint::x86::sse_cmp_ps::<2>(a, b) };
+
+    assert_eq!((lt.0.to_bits(), lt.1.to_bits(), lt.2.to_bits()), (!0, 0, 0));
+    assert_eq!((le.0.to_bits(), le.1.to_bits(), le.2.to_bits()), (!0, !0, 0));
+}
+
+// F16C takes and returns the bits of half-precision floats as `i16`
+// vectors, which are passed like any other integer vector.
+#[test]
+#[cfg(target_feature = "f16c")]
+fn f16c_vcvtps2ph() {
+    let a = f32x4(1.0, 0.5, -2.0, 65504.0);
+    let h = unsafe { llvmint::x86::vcvtps2ph_128(a, 0) };
+
+    assert_eq!(h.0 as u16, 0x3C00);
+    assert_eq!(h.1 as u16, 0x3800);
+    assert_eq!(h.2 as u16, 0xC000);
+    assert_eq!(h.3 as u16, 0x7BFF);
+    assert_eq!(h.4, 0);
+}